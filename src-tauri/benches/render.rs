@@ -0,0 +1,113 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use markpad_lib::dialect::Dialect;
+use markpad_lib::incremental::BlockCache;
+use markpad_lib::markdown::{render_markdown, RenderOptions};
+
+const README: &str = include_str!("../../README.md");
+
+/// About 5 MB of dated sections of log lines, each with code spans, a link
+/// and some emphasis, like a changelog or an exported build log.
+fn log_document() -> String {
+    let mut doc = String::from("# Build log\n\n");
+    let mut line = 0;
+    while doc.len() < 5 * 1024 * 1024 {
+        if line % 200 == 0 {
+            writeln!(doc, "\n## Run {}\n", line / 200).unwrap();
+        }
+        let level = ["INFO", "WARN", "**ERROR**", "DEBUG"][line % 4];
+        writeln!(
+            doc,
+            "- `{:02}:{:02}:{:02}` {} worker-{} handled [request {}](https://example.com/r/{}) in _{}ms_",
+            line / 3600 % 24,
+            line / 60 % 60,
+            line % 60,
+            level,
+            line % 8,
+            line,
+            line,
+            line % 500
+        )
+        .unwrap();
+        line += 1;
+    }
+    doc
+}
+
+/// Sections of wide tables with inline formatting in the cells.
+fn table_document() -> String {
+    let mut doc = String::from("# Tables\n\n");
+    for table in 0..100 {
+        writeln!(doc, "## Table {}\n", table).unwrap();
+        doc.push_str("| Name | Kind | Size | Status | Owner | Notes |\n");
+        doc.push_str("|:-----|:----:|-----:|--------|-------|-------|\n");
+        for row in 0..50 {
+            writeln!(
+                doc,
+                "| `item-{}-{}` | **{}** | {} KB | ~~old~~ new | @user{} | see [docs](#table-{}) |",
+                table,
+                row,
+                ["file", "dir", "link"][row % 3],
+                row * 37 % 1000,
+                row % 12,
+                table
+            )
+            .unwrap();
+        }
+        doc.push('\n');
+    }
+    doc
+}
+
+fn documents() -> Vec<(&'static str, String)> {
+    vec![("readme", README.to_string()), ("log", log_document()), ("tables", table_document())]
+}
+
+fn full_render(c: &mut Criterion) {
+    let mut group = c.benchmark_group("full_render");
+    group.sample_size(10);
+    for (name, doc) in documents() {
+        group.throughput(Throughput::Bytes(doc.len() as u64));
+        for (dialect, sanitize) in [(Dialect::Gfm, false), (Dialect::Gfm, true), (Dialect::Obsidian, false)] {
+            let options = RenderOptions { dialect, sanitize, ..RenderOptions::default() };
+            let id = format!("{}{}", dialect.id(), if sanitize { "-sanitized" } else { "" });
+            group.bench_with_input(BenchmarkId::new(id, name), &doc, |b, doc| {
+                b.iter(|| render_markdown(doc, None, options))
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Re-rendering after a one-line edit at the end, with the viewer already
+/// holding every block from the previous render.
+fn incremental_render(c: &mut Criterion) {
+    let mut group = c.benchmark_group("incremental_render");
+    group.sample_size(10);
+    let options = RenderOptions::default();
+    for (name, doc) in documents() {
+        let edited = format!("{}\nOne more paragraph.\n", doc);
+        let mut cache = BlockCache::default();
+        let known: HashSet<String> = cache
+            .render(&doc, None, options, &HashSet::new())
+            .blocks
+            .into_iter()
+            .map(|block| block.key)
+            .collect();
+
+        group.throughput(Throughput::Bytes(doc.len() as u64));
+        group.bench_function(name, |b| {
+            let mut flip = false;
+            b.iter(|| {
+                flip = !flip;
+                cache.render(if flip { &edited } else { &doc }, None, options, &known)
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, full_render, incremental_render);
+criterion_main!(benches);
@@ -0,0 +1,256 @@
+use comrak::nodes::{AstNode, NodeValue, Sourcepos};
+use comrak::{parse_document, Anchorizer, Arena};
+use regex::Regex;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use crate::dialect::Dialect;
+use crate::embeds::Embeds;
+use crate::extensions;
+use crate::frontmatter;
+use crate::markdown::{self, RenderOptions};
+use crate::math::Math;
+
+static HTML_REFERENCE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"<(a|img)\b[^>]*?\b(href|src)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap());
+
+/// Average silent reading speed used for the reading time estimate.
+const WORDS_PER_MINUTE: usize = 200;
+
+/// Facts about a document, all taken from the parsed AST.
+#[derive(Serialize)]
+pub struct DocumentInfo {
+    pub outline: Vec<Heading>,
+    pub stats: TextStats,
+    pub links: Vec<Reference>,
+    pub images: Vec<Reference>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Heading {
+    pub level: u8,
+    pub text: String,
+    /// The anchor id GitHub would give the heading.
+    pub slug: String,
+    /// 1-based line in the source file.
+    pub line: usize,
+    pub children: Vec<Heading>,
+}
+
+#[derive(Serialize, Default, Debug)]
+pub struct TextStats {
+    pub words: usize,
+    pub characters: usize,
+    pub reading_time_minutes: usize,
+}
+
+/// A link target or image source found in the document.
+#[derive(Serialize, Clone, Debug)]
+pub struct Reference {
+    pub url: String,
+    pub text: String,
+    pub line: usize,
+}
+
+#[derive(Default)]
+struct Collected {
+    headings: Vec<Heading>,
+    stats: TextStats,
+    links: Vec<Reference>,
+    images: Vec<Reference>,
+}
+
+/// Outline, text stats and references of `content`, from the same parse the
+/// viewer renders but without rendering it. Embedded notes aren't read, so
+/// only this document's own text counts.
+pub fn document_info(content: &str, path: Option<&Path>, options: RenderOptions) -> DocumentInfo {
+    let profile = options.profile();
+    let base_dir = path.and_then(|p| p.parent());
+    let (_, content) = frontmatter::extract(content);
+
+    let mut stack: Vec<PathBuf> = Vec::new();
+    let mut embeds = Embeds::new(base_dir, options, &mut stack);
+    let processed = markdown::preprocess(&content, &profile, base_dir, &mut embeds, &mut Math::new());
+
+    let arena = Arena::new();
+    let root = parse_document(&arena, &processed, &markdown::comrak_options(&profile, true));
+    extensions::apply(&arena, root, &profile);
+
+    let mut collected = Collected { headings: headings(root), ..Collected::default() };
+    collect(root, &mut collected);
+    let mut stats = collected.stats;
+    stats.reading_time_minutes = stats.words.div_ceil(WORDS_PER_MINUTE);
+
+    DocumentInfo {
+        outline: nest_headings(collected.headings),
+        stats,
+        links: collected.links,
+        images: collected.images,
+    }
+}
+
+/// Heading tree of `content`, read straight from the comrak AST without
+/// rendering. Lines refer to the source file, front matter included.
+pub fn outline(content: &str, dialect: Dialect) -> Vec<Heading> {
+    let (_, content) = frontmatter::extract(content);
+    let arena = Arena::new();
+    let root = parse_document(&arena, &content, &markdown::comrak_options(&dialect.profile(), true));
+    heading_tree(root)
+}
+
+/// Nested headings of a parsed document, with the same anchor ids comrak's
+/// `header_ids` extension gives them.
+pub fn heading_tree<'a>(root: &'a AstNode<'a>) -> Vec<Heading> {
+    nest_headings(headings(root))
+}
+
+fn headings<'a>(root: &'a AstNode<'a>) -> Vec<Heading> {
+    let mut anchorizer = Anchorizer::new();
+    root.descendants()
+        .filter_map(|node| match node.data.borrow().value {
+            NodeValue::Heading(ref heading) => Some((heading.level, node)),
+            _ => None,
+        })
+        .map(|(level, node)| {
+            let text = plain_text(node);
+            Heading { level, slug: anchorizer.anchorize(text.clone()), text, line: line_of(node), children: Vec::new() }
+        })
+        .collect()
+}
+
+fn collect<'a>(node: &'a AstNode<'a>, out: &mut Collected) {
+    match &node.data.borrow().value {
+        NodeValue::Link(link) => out.links.push(Reference { url: link.url.clone(), text: plain_text(node), line: line_of(node) }),
+        NodeValue::Image(link) => out.images.push(Reference { url: link.url.clone(), text: plain_text(node), line: line_of(node) }),
+        // Counted per block, since `un*believ*able` is one word split over
+        // several text nodes
+        NodeValue::Paragraph | NodeValue::Heading(_) | NodeValue::TableCell => count_text(&plain_text(node), &mut out.stats),
+        NodeValue::HtmlInline(html) => collect_html_references(html, line_of(node), out),
+        NodeValue::HtmlBlock(block) => collect_html_references(&block.literal, line_of(node), out),
+        _ => {}
+    }
+
+    for child in node.children() {
+        collect(child, out);
+    }
+}
+
+fn count_text(text: &str, stats: &mut TextStats) {
+    stats.words += text.split_whitespace().count();
+    stats.characters += text.chars().count();
+}
+
+/// Picks up `<a href>` and `<img src>` written as raw HTML, which is also what
+/// wiki-links and `![[...]]` image embeds turn into.
+fn collect_html_references(html: &str, line: usize, out: &mut Collected) {
+    for (url, is_image) in html_references(html) {
+        let reference = Reference { url, text: String::new(), line };
+        if is_image {
+            out.images.push(reference);
+        } else {
+            out.links.push(reference);
+        }
+    }
+}
+
+/// The targets of `<a href>` and `<img src>` tags in raw HTML, each with
+/// whether it is an image.
+pub fn html_references(html: &str) -> Vec<(String, bool)> {
+    HTML_REFERENCE
+        .captures_iter(html)
+        .filter_map(|caps| {
+            let url = caps.get(3).or_else(|| caps.get(4)).map(|m| markdown::unescape_html(m.as_str())).unwrap_or_default();
+            match (&caps[1], &caps[2]) {
+                ("a", "href") => Some((url, false)),
+                ("img", "src") => Some((url, true)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Concatenated text of a node's inline content, as comrak uses for heading ids.
+pub fn plain_text<'a>(node: &'a AstNode<'a>) -> String {
+    let mut text = String::new();
+    for descendant in node.descendants() {
+        match &descendant.data.borrow().value {
+            NodeValue::Text(t) => text.push_str(t),
+            NodeValue::Code(code) => text.push_str(&code.literal),
+            NodeValue::SoftBreak | NodeValue::LineBreak => text.push(' '),
+            _ => {}
+        }
+    }
+    text
+}
+
+/// A node's position. Some inlines carry none, so fall back to the closest
+/// ancestor that does, on its first line.
+pub fn position<'a>(node: &'a AstNode<'a>) -> Sourcepos {
+    let pos = node.data.borrow().sourcepos;
+    if pos.start.line > 0 {
+        return pos;
+    }
+    let start = node.ancestors().map(|n| n.data.borrow().sourcepos.start).find(|start| start.line > 0).unwrap_or(pos.start);
+    Sourcepos { start, end: (start.line, usize::MAX - 1).into() }
+}
+
+fn line_of<'a>(node: &'a AstNode<'a>) -> usize {
+    position(node).start.line
+}
+
+/// Turns a flat heading list into a tree where each heading holds the
+/// deeper headings that follow it.
+fn nest_headings(flat: Vec<Heading>) -> Vec<Heading> {
+    let mut roots: Vec<Heading> = Vec::new();
+    let mut open: Vec<Heading> = Vec::new();
+
+    for heading in flat {
+        while open.last().is_some_and(|h| h.level >= heading.level) {
+            let done = open.pop().unwrap();
+            attach(&mut roots, &mut open, done);
+        }
+        open.push(heading);
+    }
+    while let Some(done) = open.pop() {
+        attach(&mut roots, &mut open, done);
+    }
+
+    roots
+}
+
+fn attach(roots: &mut Vec<Heading>, open: &mut [Heading], heading: Heading) {
+    match open.last_mut() {
+        Some(parent) => parent.children.push(heading),
+        None => roots.push(heading),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(content: &str) -> DocumentInfo {
+        document_info(content, None, RenderOptions::default())
+    }
+
+    #[test]
+    fn words_split_by_emphasis_count_once() {
+        let stats = info("un*believ*able `code` [a link](x.md)\n").stats;
+        assert_eq!(stats.words, 4);
+        assert_eq!(stats.characters, "unbelievable code a link".len());
+
+        let stats = info("# Title\n\none\ntwo\n\n| a | b c |\n|---|---|\n").stats;
+        assert_eq!(stats.words, 6);
+        assert_eq!(stats.reading_time_minutes, 1);
+    }
+
+    #[test]
+    fn collects_links_images_and_headings() {
+        let info = info("# A\n\n[x](a.md) ![y](b.png) <img src=\"c.png\">\n\n## B\n");
+        assert_eq!(info.links.iter().map(|l| l.url.as_str()).collect::<Vec<_>>(), ["a.md"]);
+        assert_eq!(info.images.iter().map(|i| i.url.as_str()).collect::<Vec<_>>(), ["b.png", "c.png"]);
+        assert_eq!(info.outline.len(), 1);
+        assert_eq!(info.outline[0].children[0].text, "B");
+        assert_eq!(info.outline[0].children[0].line, 5);
+    }
+}
@@ -0,0 +1,203 @@
+use comrak::nodes::{Ast, AstNode, NodeHtmlBlock, NodeValue};
+use comrak::Arena;
+use regex::Regex;
+use std::cell::RefCell;
+use std::sync::LazyLock;
+
+use crate::analysis::plain_text;
+use crate::markdown::escape_html;
+
+static MARKER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\[!([A-Za-z][\w-]*)\]([+-]?)(?:[ \t]|$)").unwrap());
+
+/// A blockquote opening with a `[!type]` marker, as in GitHub alerts
+/// (`> [!NOTE]`) and Obsidian callouts (`> [!tip]- Custom title`).
+struct Callout {
+    kind: String,
+    title: Option<String>,
+    /// `Some(open)` for `+` (open) and `-` (closed) foldable callouts.
+    fold: Option<bool>,
+}
+
+/// Turns callout blockquotes into GitHub's alert markup,
+/// `<div class="markdown-alert markdown-alert-<style>">` with a title, or a
+/// `<details>` with the same classes for foldable ones. The quote's content
+/// is kept as it is, so it still carries its source positions.
+pub fn expand<'a>(arena: &'a Arena<AstNode<'a>>, root: &'a AstNode<'a>) {
+    let quotes: Vec<_> = root
+        .descendants()
+        .filter(|node| matches!(node.data.borrow().value, NodeValue::BlockQuote))
+        .collect();
+
+    for quote in quotes {
+        let Some(callout) = take_marker(quote) else {
+            continue;
+        };
+        let (open, close) = render_wrapper(&callout);
+
+        quote.insert_before(html_block(arena, open));
+        for child in quote.children().collect::<Vec<_>>() {
+            quote.insert_before(child);
+        }
+        quote.insert_before(html_block(arena, close));
+        quote.detach();
+    }
+}
+
+/// Removes the marker line from the quote's first paragraph and returns what
+/// it said, or `None` if the quote isn't a callout.
+fn take_marker<'a>(quote: &'a AstNode<'a>) -> Option<Callout> {
+    let paragraph = quote.first_child().filter(|p| matches!(p.data.borrow().value, NodeValue::Paragraph))?;
+
+    // comrak splits `[` and `]` into text nodes of their own, so join the
+    // leading text before matching
+    let mut leading = String::new();
+    for child in paragraph.children() {
+        match &child.data.borrow().value {
+            NodeValue::Text(t) => leading.push_str(t),
+            _ => break,
+        }
+    }
+    let caps = MARKER.captures(&leading)?;
+    let kind = caps[1].to_ascii_lowercase();
+    let fold = match &caps[2] {
+        "+" => Some(true),
+        "-" => Some(false),
+        _ => None,
+    };
+
+    let first_line: Vec<_> = paragraph
+        .children()
+        .take_while(|c| !matches!(c.data.borrow().value, NodeValue::SoftBreak | NodeValue::LineBreak))
+        .collect();
+    let mut title: String = first_line.iter().map(|node| plain_text(node)).collect();
+    title.replace_range(..caps[0].len(), "");
+    let title = Some(title.trim().to_string()).filter(|t| !t.is_empty());
+
+    for node in first_line {
+        node.detach();
+    }
+    if let Some(line_break) = paragraph.first_child() {
+        line_break.detach();
+    }
+    if paragraph.first_child().is_none() {
+        paragraph.detach();
+    }
+
+    Some(Callout { kind, title, fold })
+}
+
+fn render_wrapper(callout: &Callout) -> (String, String) {
+    let title = callout.title.clone().unwrap_or_else(|| default_title(&callout.kind));
+    let attributes = format!(
+        "class=\"markdown-alert markdown-alert-{}\" data-callout=\"{}\"",
+        style(&callout.kind),
+        escape_html(&callout.kind)
+    );
+
+    match callout.fold {
+        Some(open) => (
+            format!(
+                "<details {}{}>\n<summary class=\"markdown-alert-title\">{}</summary>\n",
+                attributes,
+                if open { " open" } else { "" },
+                escape_html(&title)
+            ),
+            "</details>\n".to_string(),
+        ),
+        None => (
+            format!("<div {}>\n<p class=\"markdown-alert-title\">{}</p>\n", attributes, escape_html(&title)),
+            "</div>\n".to_string(),
+        ),
+    }
+}
+
+/// `note` -> `Note`, `to-do` -> `To do`.
+fn default_title(kind: &str) -> String {
+    let mut title = kind.replace(['-', '_'], " ");
+    if let Some(first) = title.get(..1) {
+        let upper = first.to_ascii_uppercase();
+        title.replace_range(..1, &upper);
+    }
+    title
+}
+
+/// Groups GitHub's five alert types and Obsidian's callout types and aliases
+/// into GitHub's five alert styles. Unknown types look like notes.
+fn style(kind: &str) -> &'static str {
+    match kind {
+        "tip" | "hint" | "success" | "check" | "done" => "tip",
+        "important" | "example" => "important",
+        "warning" | "attention" | "question" | "help" | "faq" => "warning",
+        "caution" | "danger" | "error" | "failure" | "fail" | "missing" | "bug" => "caution",
+        _ => "note",
+    }
+}
+
+fn html_block<'a>(arena: &'a Arena<AstNode<'a>>, literal: String) -> &'a AstNode<'a> {
+    let value = NodeValue::HtmlBlock(NodeHtmlBlock { block_type: 6, literal });
+    arena.alloc(AstNode::new(RefCell::new(Ast::new(value, (0, 0).into()))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::Dialect;
+    use crate::markdown;
+    use comrak::{format_html, parse_document};
+
+    fn render(content: &str) -> String {
+        let options = markdown::comrak_options(&Dialect::Gfm.profile(), false);
+        let arena = Arena::new();
+        let root = parse_document(&arena, content, &options);
+        expand(&arena, root);
+        let mut html = Vec::new();
+        format_html(root, &options, &mut html).unwrap();
+        String::from_utf8(html).unwrap()
+    }
+
+    #[test]
+    fn renders_github_alerts() {
+        assert_eq!(
+            render("> [!NOTE]\n> Useful *info*."),
+            concat!(
+                "<div class=\"markdown-alert markdown-alert-note\" data-callout=\"note\">\n",
+                "<p class=\"markdown-alert-title\">Note</p>\n",
+                "<p>Useful <em>info</em>.</p>\n",
+                "</div>\n",
+            )
+        );
+    }
+
+    #[test]
+    fn renders_obsidian_callouts_with_titles_and_folding() {
+        assert_eq!(
+            render("> [!faq]- Why 1 < 2?\n> Because."),
+            concat!(
+                "<details class=\"markdown-alert markdown-alert-warning\" data-callout=\"faq\">\n",
+                "<summary class=\"markdown-alert-title\">Why 1 &lt; 2?</summary>\n",
+                "<p>Because.</p>\n",
+                "</details>\n",
+            )
+        );
+        assert!(render("> [!tip]+\n> Open.").starts_with("<details class=\"markdown-alert markdown-alert-tip\" data-callout=\"tip\" open>\n<summary class=\"markdown-alert-title\">Tip</summary>"));
+    }
+
+    #[test]
+    fn names_unknown_types_after_themselves() {
+        assert!(render("> [!to-do]\n> x").contains("markdown-alert-note\" data-callout=\"to-do\">\n<p class=\"markdown-alert-title\">To do</p>"));
+    }
+
+    #[test]
+    fn keeps_quotes_without_a_marker_and_drops_empty_paragraphs() {
+        assert_eq!(render("> Just a [!NOTE] quote."), "<blockquote>\n<p>Just a [!NOTE] quote.</p>\n</blockquote>\n");
+        assert_eq!(
+            render("> [!WARNING]\n\n> next"),
+            concat!(
+                "<div class=\"markdown-alert markdown-alert-warning\" data-callout=\"warning\">\n",
+                "<p class=\"markdown-alert-title\">Warning</p>\n",
+                "</div>\n",
+                "<blockquote>\n<p>next</p>\n</blockquote>\n",
+            )
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use std::fs;
+use std::path::Path;
+
+use crate::dialect::Dialect;
+use crate::export;
+use crate::files;
+use crate::linkcheck;
+use crate::markdown::RenderOptions;
+use crate::settings::Settings;
+
+const USAGE: &str = "Usage: Markpad --export <output.html> <input.md> [--dialect=markpad|gfm|commonmark|obsidian]
+       Markpad --check <file.md|folder> [--dialect=markpad|gfm|commonmark|obsidian]";
+
+/// Handles headless invocations such as `Markpad --export out.html file.md`
+/// or `Markpad --check notes/`.
+///
+/// Returns `None` when the arguments don't ask for a headless mode, so the
+/// caller can go on to start the GUI. Otherwise returns the process exit code:
+/// `0` on success, `1` when reading, rendering or writing fails (or, for
+/// `--check`, when broken links are found) and `2` for malformed arguments.
+/// Without `--dialect` the dialect saved for the input file, or the default
+/// one, is used. Raw HTML is sanitized unless the input is in a folder
+/// trusted in the app.
+pub fn run_headless(args: &[String]) -> Option<i32> {
+    if !args.iter().any(|arg| arg == "--check" || arg == "--export") {
+        return None;
+    }
+    attach_console();
+
+    if let Some(pos) = args.iter().position(|arg| arg == "--check") {
+        return Some(run_check(&args[pos + 1..]));
+    }
+    let pos = args.iter().position(|arg| arg == "--export")?;
+    let rest: Vec<&String> = args[pos + 1..].iter().filter(|arg| !arg.starts_with('-')).collect();
+
+    let (output, input) = match rest.as_slice() {
+        [output, input] => (output.as_str(), input.as_str()),
+        _ => {
+            eprintln!("{}", USAGE);
+            return Some(2);
+        }
+    };
+
+    let mut options = Settings::load().render_options(Some(Path::new(input)));
+    match dialect_arg(args) {
+        Ok(Some(dialect)) => options.dialect = dialect,
+        Ok(None) => {}
+        Err(code) => return Some(code),
+    }
+
+    match export_file(Path::new(input), Path::new(output), options) {
+        Ok(()) => Some(0),
+        Err(e) => {
+            eprintln!("Markpad: {}", e);
+            Some(1)
+        }
+    }
+}
+
+/// `--dialect=...`, if given. Reports an unknown one and returns exit code 2.
+fn dialect_arg(args: &[String]) -> Result<Option<Dialect>, i32> {
+    let Some(id) = args.iter().find_map(|arg| arg.strip_prefix("--dialect=")) else {
+        return Ok(None);
+    };
+    match Dialect::from_id(id) {
+        Some(dialect) => Ok(Some(dialect)),
+        None => {
+            eprintln!("Markpad: unknown dialect '{}'", id);
+            eprintln!("{}", USAGE);
+            Err(2)
+        }
+    }
+}
+
+/// Prints each broken link as `file:line:column: message`, the format
+/// editors and CI logs pick up.
+fn run_check(args: &[String]) -> i32 {
+    let rest: Vec<&String> = args.iter().filter(|arg| !arg.starts_with('-')).collect();
+    let [input] = rest.as_slice() else {
+        eprintln!("{}", USAGE);
+        return 2;
+    };
+    let dialect = match dialect_arg(args) {
+        Ok(dialect) => dialect,
+        Err(code) => return code,
+    };
+
+    let settings = Settings::load();
+    let dialect_for = |path: &Path| dialect.unwrap_or_else(|| settings.dialect_for(Some(path)));
+    let input = Path::new(input.as_str());
+    let result = if input.is_dir() {
+        linkcheck::check_folder(input, &dialect_for)
+    } else {
+        files::read_to_string(input)
+            .map_err(|e| format!("failed to read {}: {}", input.display(), e))
+            .map(|content| linkcheck::check_document(&content, Some(input), dialect_for(input), &dialect_for))
+    };
+
+    match result {
+        Ok(broken) if broken.is_empty() => 0,
+        Ok(broken) => {
+            for link in &broken {
+                println!("{}:{}:{}: {}", link.file, link.line, link.column, link.message);
+            }
+            1
+        }
+        Err(e) => {
+            eprintln!("Markpad: {}", e);
+            1
+        }
+    }
+}
+
+fn export_file(input: &Path, output: &Path, options: RenderOptions) -> Result<(), String> {
+    let content = files::read_to_string(input).map_err(|e| format!("failed to read {}: {}", input.display(), e))?;
+    let html = export::export_document(&content, input, options);
+
+    fs::write(output, html).map_err(|e| format!("failed to write {}: {}", output.display(), e))
+}
+
+/// Release builds are GUI programs on Windows and start without a console,
+/// so anything printed would be lost. Borrow the console of the shell that
+/// started us, if there is one.
+#[cfg(target_os = "windows")]
+fn attach_console() {
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn AttachConsole(process_id: u32) -> i32;
+    }
+
+    // Fails harmlessly when there is no parent console or we already have one
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn attach_console() {}
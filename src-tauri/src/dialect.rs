@@ -0,0 +1,144 @@
+use serde::{Deserialize, Serialize};
+
+/// A named set of rendering options.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Dialect {
+    /// What Markpad rendered before dialects existed: GFM plus hard line
+    /// breaks, definition lists and `![[embeds]]`.
+    #[default]
+    Markpad,
+    /// GitHub Flavored Markdown, as GitHub renders a `.md` file.
+    Gfm,
+    /// The CommonMark spec with no extensions.
+    Commonmark,
+    /// Obsidian notes: GFM plus hard line breaks, wiki-links, embeds and
+    /// `==highlights==`.
+    Obsidian,
+}
+
+/// What a dialect turns on. The first group maps to comrak's extension and
+/// render options, the rest to Markpad's own passes.
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct DialectProfile {
+    pub strikethrough: bool,
+    pub table: bool,
+    pub autolink: bool,
+    pub tasklist: bool,
+    pub superscript: bool,
+    pub subscript: bool,
+    pub footnotes: bool,
+    pub description_lists: bool,
+    pub header_ids: bool,
+    pub hardbreaks: bool,
+    pub raw_html: bool,
+    pub wikilinks: bool,
+    pub embeds: bool,
+    pub toc: bool,
+    pub math: bool,
+    pub callouts: bool,
+    pub mark: bool,
+    pub emoji: bool,
+}
+
+pub const ALL_DIALECTS: [Dialect; 4] = [Dialect::Markpad, Dialect::Gfm, Dialect::Commonmark, Dialect::Obsidian];
+
+impl Dialect {
+    pub fn id(self) -> &'static str {
+        match self {
+            Dialect::Markpad => "markpad",
+            Dialect::Gfm => "gfm",
+            Dialect::Commonmark => "commonmark",
+            Dialect::Obsidian => "obsidian",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Dialect::Markpad => "Markpad (legacy)",
+            Dialect::Gfm => "GitHub (GFM)",
+            Dialect::Commonmark => "CommonMark (strict)",
+            Dialect::Obsidian => "Obsidian",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Dialect> {
+        ALL_DIALECTS.into_iter().find(|d| d.id().eq_ignore_ascii_case(id))
+    }
+
+    pub fn profile(self) -> DialectProfile {
+        let gfm = DialectProfile {
+            strikethrough: true,
+            table: true,
+            autolink: true,
+            tasklist: true,
+            superscript: false,
+            subscript: false,
+            footnotes: true,
+            description_lists: false,
+            header_ids: true,
+            hardbreaks: false,
+            raw_html: true,
+            wikilinks: false,
+            embeds: false,
+            toc: true,
+            math: true,
+            callouts: true,
+            mark: false,
+            emoji: true,
+        };
+
+        match self {
+            Dialect::Markpad => DialectProfile {
+                description_lists: true,
+                hardbreaks: true,
+                embeds: true,
+                ..gfm
+            },
+            Dialect::Gfm => gfm,
+            Dialect::Commonmark => DialectProfile {
+                strikethrough: false,
+                table: false,
+                autolink: false,
+                tasklist: false,
+                footnotes: false,
+                header_ids: false,
+                toc: false,
+                math: false,
+                callouts: false,
+                emoji: false,
+                ..gfm
+            },
+            Dialect::Obsidian => DialectProfile {
+                description_lists: true,
+                hardbreaks: true,
+                wikilinks: true,
+                embeds: true,
+                mark: true,
+                ..gfm
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_default_keeps_the_options_markpad_always_used() {
+        let profile = Dialect::default().profile();
+        assert!(profile.hardbreaks && profile.description_lists && profile.embeds && profile.raw_html);
+        assert!(!Dialect::Gfm.profile().hardbreaks);
+    }
+
+    #[test]
+    fn ids_round_trip() {
+        for dialect in ALL_DIALECTS {
+            assert_eq!(Dialect::from_id(dialect.id()), Some(dialect));
+            assert_eq!(serde_json::to_value(dialect).unwrap(), dialect.id());
+        }
+        assert_eq!(Dialect::from_id("GFM"), Some(Dialect::Gfm));
+        assert_eq!(Dialect::from_id("markdown"), None);
+    }
+}
@@ -0,0 +1,465 @@
+use comrak::nodes::{Ast, AstNode, NodeHtmlBlock, NodeValue};
+use comrak::{parse_document, Anchorizer, Arena, ComrakOptions};
+use regex::{Regex, Captures};
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use crate::analysis::plain_text;
+use crate::files;
+use crate::markdown::{self, escape_html, RenderOptions};
+use crate::wikilinks;
+
+static EMBED: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"!\[\[(.*?)\]\]").unwrap());
+static PLACEHOLDER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"<div data-markpad-embed="(\d+)"></div>"#).unwrap());
+static PENDING: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"^<span data-markpad-embed="(\d+)">$"#).unwrap());
+static LIST_ITEM: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\s*([-*+]|\d+[.)])\s").unwrap());
+
+/// How many notes deep `![[...]]` transclusions are followed.
+const MAX_EMBED_DEPTH: usize = 4;
+
+/// Expands Obsidian `![[...]]` embeds. Images become `<img>` tags; Markdown
+/// notes are rendered and later spliced into the output in place of a
+/// placeholder, so their HTML never goes back through the Markdown parser.
+///
+/// A note is only transcluded when its embed is alone on its line, which
+/// `place` works out from the parsed document. Anywhere else it would put a
+/// block inside a paragraph, so it becomes a wiki-link to the note instead.
+pub struct Embeds<'a> {
+    base_dir: Option<&'a Path>,
+    options: RenderOptions,
+    stack: &'a mut Vec<PathBuf>,
+    /// What is inside the brackets of each note embed seen by `process`.
+    notes: Vec<String>,
+    rendered: Vec<String>,
+}
+
+impl<'a> Embeds<'a> {
+    /// `stack` holds the canonical paths of the notes currently being
+    /// rendered, outermost first, and is used to detect cycles. Embedded
+    /// notes are rendered with the same `options` as the note embedding them.
+    pub fn new(base_dir: Option<&'a Path>, options: RenderOptions, stack: &'a mut Vec<PathBuf>) -> Self {
+        Embeds { base_dir, options, stack, notes: Vec::new(), rendered: Vec::new() }
+    }
+
+    pub fn process(&mut self, content: &str) -> String {
+        EMBED.replace_all(content, |caps: &Captures| {
+            let embed = parse_embed(&caps[1]);
+
+            if note_target(embed.target).is_some() {
+                self.notes.push(caps[1].to_string());
+                return format!("<span data-markpad-embed=\"{}\"></span>", self.notes.len() - 1);
+            }
+
+            image_tag(&embed)
+        })
+        .into_owned()
+    }
+
+    /// Replaces each note placeholder left by `process` with the note, when
+    /// it is alone on a line of a paragraph, or with a link to it. The
+    /// paragraph is split around a transcluded note, so the note's HTML ends
+    /// up between paragraphs rather than inside one.
+    pub fn place<'n>(&mut self, arena: &'n Arena<AstNode<'n>>, root: &'n AstNode<'n>) {
+        let placeholders: Vec<_> = root
+            .descendants()
+            .filter_map(|node| match &node.data.borrow().value {
+                NodeValue::HtmlInline(html) => PENDING.captures(html).and_then(|caps| caps[1].parse::<usize>().ok()),
+                _ => None,
+            }
+            .map(|index| (node, index)))
+            .collect();
+
+        for (node, index) in placeholders {
+            let Some(inner) = self.notes.get(index).cloned() else {
+                continue;
+            };
+            // comrak gives the opening and closing tags a node each
+            if let Some(close) = node.next_sibling().filter(|n| matches!(&n.data.borrow().value, NodeValue::HtmlInline(html) if html == "</span>")) {
+                close.detach();
+            }
+            let paragraph = node.parent().filter(|p| matches!(p.data.borrow().value, NodeValue::Paragraph));
+            match paragraph.filter(|_| is_line_edge(node.previous_sibling()) && is_line_edge(node.next_sibling())) {
+                Some(paragraph) => {
+                    let target = parse_embed(&inner).target;
+                    let html = self.transclude(target, note_target(target).unwrap_or(target));
+                    self.rendered.push(html);
+                    let placeholder = format!("<div data-markpad-embed=\"{}\"></div>\n", self.rendered.len() - 1);
+                    split_paragraph(arena, paragraph, node, placeholder);
+                }
+                None => {
+                    let link = wikilinks::process_wikilinks(&format!("[[{}]]", inner), self.base_dir).into_owned();
+                    node.data.borrow_mut().value = NodeValue::HtmlInline(link);
+                }
+            }
+        }
+    }
+
+    /// Replaces the placeholders left by `place` with the rendered notes.
+    pub fn splice(&self, html: &str) -> String {
+        if self.rendered.is_empty() {
+            return html.to_string();
+        }
+        PLACEHOLDER.replace_all(html, |caps: &Captures| {
+            caps[1].parse::<usize>().ok()
+                .and_then(|i| self.rendered.get(i))
+                .cloned()
+                .unwrap_or_default()
+        })
+        .into_owned()
+    }
+
+    fn transclude(&mut self, target: &str, note: &str) -> String {
+        let fragment = target.split_once('#').map(|(_, f)| f.trim()).filter(|f| !f.is_empty());
+
+        let resolved = self.base_dir
+            .and_then(|dir| wikilinks::resolve_note(dir, note).map(|rel| dir.join(rel)))
+            .and_then(|p| fs::canonicalize(p).ok());
+
+        let Some(path) = resolved else {
+            return embed_error(target, "Note not found");
+        };
+        if self.stack.contains(&path) {
+            return embed_error(target, "Embed cycle");
+        }
+        if self.stack.len() > MAX_EMBED_DEPTH {
+            return embed_error(target, "Embeds nested too deeply");
+        }
+
+        let content = match files::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) => return embed_error(target, &e.to_string()),
+        };
+        let content = match fragment {
+            Some(fragment) => extract_fragment(&content, fragment),
+            None => Some(content),
+        };
+        let Some(content) = content else {
+            return embed_error(target, "Section not found");
+        };
+
+        self.stack.push(path.clone());
+        let html = markdown::render_document(&content, Some(&path), self.options, self.stack).html;
+        self.stack.pop();
+
+        format!(
+            "<div class=\"markdown-embed\" data-embed-src=\"{}\">\n{}</div>",
+            escape_html(target),
+            html
+        )
+    }
+}
+
+/// Returns the note part of `target` when it refers to a Markdown file:
+/// anything with a `.md`/`.markdown` extension or no extension at all.
+pub fn note_target(target: &str) -> Option<&str> {
+    let note = target.split('#').next().unwrap_or("").trim();
+    if note.is_empty() {
+        return None;
+    }
+    match Path::new(note).extension().map(|e| e.to_string_lossy().to_lowercase()) {
+        Some(ext) if ext == "md" || ext == "markdown" => Some(note),
+        Some(_) => None,
+        None => Some(note),
+    }
+}
+
+/// The part of a note a `#heading` or `#^block-id` fragment refers to.
+pub fn extract_fragment(content: &str, fragment: &str) -> Option<String> {
+    match fragment.strip_prefix('^') {
+        Some(id) => extract_block(content, id),
+        None => extract_section(content, fragment),
+    }
+}
+
+/// Whether a paragraph's line starts or ends next to `node`, the sibling
+/// before or after an inline.
+fn is_line_edge<'a>(node: Option<&'a AstNode<'a>>) -> bool {
+    node.is_none_or(|n| matches!(n.data.borrow().value, NodeValue::SoftBreak | NodeValue::LineBreak))
+}
+
+/// Replaces `embed` and the line breaks around it with an HTML block
+/// holding `html`, moving what came before it into a paragraph of its own.
+/// The pieces get the lines they came from, which the viewer relies on.
+fn split_paragraph<'a>(arena: &'a Arena<AstNode<'a>>, paragraph: &'a AstNode<'a>, embed: &'a AstNode<'a>, html: String) {
+    let before: Vec<_> = embed.preceding_siblings().skip(1).collect();
+    let pos = paragraph.data.borrow().sourcepos;
+    let line = pos.start.line
+        + before
+            .iter()
+            .flat_map(|node| node.descendants())
+            .filter(|node| matches!(node.data.borrow().value, NodeValue::SoftBreak | NodeValue::LineBreak))
+            .count();
+
+    if let Some(line_break) = embed.previous_sibling() {
+        line_break.detach();
+    }
+    if let Some(line_break) = embed.next_sibling() {
+        line_break.detach();
+    }
+    if before.len() > 1 {
+        let head = arena.alloc(AstNode::new(RefCell::new(Ast::new(NodeValue::Paragraph, pos.start))));
+        head.data.borrow_mut().sourcepos.end = (line - 1, 0).into();
+        for node in before.into_iter().skip(1).rev() {
+            head.append(node);
+        }
+        paragraph.insert_before(head);
+    }
+
+    let value = NodeValue::HtmlBlock(NodeHtmlBlock { block_type: 6, literal: html });
+    let block = arena.alloc(AstNode::new(RefCell::new(Ast::new(value, (line, 1).into()))));
+    paragraph.insert_before(block);
+    embed.detach();
+
+    if paragraph.first_child().is_none() {
+        paragraph.detach();
+    } else {
+        paragraph.data.borrow_mut().sourcepos.start = (line + 1, 1).into();
+    }
+}
+
+/// The parts of `![[target|alt|size]]`. Obsidian puts an optional size
+/// last, as `width` or `widthxheight`; whatever else follows the target is
+/// the alt text (or, for notes, a display alias that is ignored).
+#[derive(Debug, PartialEq)]
+struct EmbedSyntax<'t> {
+    target: &'t str,
+    alt: Option<&'t str>,
+    size: Option<(u32, Option<u32>)>,
+}
+
+fn parse_embed(inner: &str) -> EmbedSyntax<'_> {
+    let Some((target, rest)) = inner.split_once('|') else {
+        return EmbedSyntax { target: inner.trim(), alt: None, size: None };
+    };
+    let (alt, last) = match rest.rsplit_once('|') {
+        Some((alt, last)) => (Some(alt), last),
+        None => (None, rest),
+    };
+    let (alt, size) = match parse_size(last) {
+        Some(size) => (alt, Some(size)),
+        None => (Some(rest), None),
+    };
+    EmbedSyntax {
+        target: target.trim(),
+        alt: alt.map(str::trim).filter(|alt| !alt.is_empty()),
+        size,
+    }
+}
+
+/// `100` or `100x200`, digits only.
+fn parse_size(size: &str) -> Option<(u32, Option<u32>)> {
+    let size = size.trim();
+    match size.split_once('x') {
+        Some((width, height)) => Some((dimension(width)?, Some(dimension(height)?))),
+        None => Some((dimension(size)?, None)),
+    }
+}
+
+fn dimension(text: &str) -> Option<u32> {
+    if text.is_empty() || !text.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    text.parse().ok()
+}
+
+fn image_tag(embed: &EmbedSyntax) -> String {
+    let mut tag = format!("<img src=\"{}\"", escape_html(embed.target));
+    if let Some((width, height)) = embed.size {
+        tag.push_str(&format!(" width=\"{}\"", width));
+        if let Some(height) = height {
+            tag.push_str(&format!(" height=\"{}\"", height));
+        }
+    }
+    tag.push_str(&format!(" alt=\"{}\" />", escape_html(embed.alt.unwrap_or(embed.target))));
+    tag
+}
+
+fn embed_error(target: &str, reason: &str) -> String {
+    format!(
+        "<div class=\"markdown-embed markdown-embed-error\">{}: {}</div>",
+        escape_html(reason),
+        escape_html(target)
+    )
+}
+
+/// Extracts the section under the heading matching `heading`, up to the next
+/// heading of the same or a higher level. Headings are compared by their
+/// anchor slug, so `#Set up` and `#set-up` both match `## Set Up`. Only
+/// top-level headings count, as comrak parses them, so `#` lines in code
+/// blocks are never taken for headings.
+fn extract_section(content: &str, heading: &str) -> Option<String> {
+    let wanted = Anchorizer::new().anchorize(heading.to_string());
+    let arena = Arena::new();
+    let root = parse_document(&arena, content, &ComrakOptions::default());
+    let headings: Vec<(u8, usize, String)> = root
+        .children()
+        .filter_map(|node| match node.data.borrow().value {
+            NodeValue::Heading(h) => Some((h.level, node.data.borrow().sourcepos.start.line, plain_text(node))),
+            _ => None,
+        })
+        .collect();
+
+    let index = headings.iter().position(|(_, _, text)| Anchorizer::new().anchorize(text.clone()) == wanted)?;
+    let (level, start, _) = headings[index];
+    let end = headings[index + 1..].iter().find(|(l, _, _)| *l <= level).map(|(_, line, _)| line - 1);
+
+    let lines = content.lines().skip(start - 1).take(end.map_or(usize::MAX, |end| end + 1 - start));
+    Some(lines.map(|line| format!("{}\n", line)).collect())
+}
+
+/// Extracts the block tagged with an Obsidian `^block-id`: the list item or
+/// paragraph whose last line ends with the marker, without the marker.
+fn extract_block(content: &str, id: &str) -> Option<String> {
+    let marker = format!("^{}", id);
+    let lines: Vec<&str> = content.lines().collect();
+    let index = lines.iter().position(|line| {
+        line.trim_end().strip_suffix(marker.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.ends_with([' ', '\t']))
+    })?;
+
+    let tagged = lines[index].trim_end();
+    let tagged = tagged[..tagged.len() - marker.len()].trim_end();
+
+    let is_list_item = LIST_ITEM.is_match(tagged);
+    if is_list_item {
+        return Some(format!("{}\n", tagged.trim_start()));
+    }
+
+    // A marker on a line of its own tags the block just above it
+    let mut end = index;
+    if tagged.is_empty() {
+        while end > 0 && lines[end - 1].trim().is_empty() {
+            end -= 1;
+        }
+        if end == 0 {
+            return None;
+        }
+    }
+    let start = lines[..end].iter().rposition(|line| line.trim().is_empty()).map_or(0, |i| i + 1);
+    let mut block: Vec<&str> = lines[start..end].to_vec();
+    if !tagged.is_empty() {
+        block.push(tagged);
+    }
+    Some(format!("{}\n", block.join("\n")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::Dialect;
+
+    fn render(content: &str) -> String {
+        let mut stack = Vec::new();
+        let mut embeds = Embeds::new(None, RenderOptions::default(), &mut stack);
+        let processed = embeds.process(content);
+        embeds.splice(&processed)
+    }
+
+    /// Runs the whole pipeline, which decides where notes go. Rendered as an
+    /// embedded note, so without `data-sourcepos` attributes.
+    fn render_note(content: &str) -> String {
+        let options = RenderOptions { dialect: Dialect::Obsidian, ..RenderOptions::default() };
+        let mut stack = vec![PathBuf::from("outer.md"), PathBuf::from("note.md")];
+        markdown::render_document(content, None, options, &mut stack).html
+    }
+
+    #[test]
+    fn parses_sizes_and_alt_text() {
+        assert_eq!(parse_embed("a.png"), EmbedSyntax { target: "a.png", alt: None, size: None });
+        assert_eq!(parse_embed("a.png|100"), EmbedSyntax { target: "a.png", alt: None, size: Some((100, None)) });
+        assert_eq!(parse_embed("a.png|100x50"), EmbedSyntax { target: "a.png", alt: None, size: Some((100, Some(50))) });
+        assert_eq!(
+            parse_embed("a.png|A cat|300"),
+            EmbedSyntax { target: "a.png", alt: Some("A cat"), size: Some((300, None)) }
+        );
+        assert_eq!(parse_embed("a.png|A cat"), EmbedSyntax { target: "a.png", alt: Some("A cat"), size: None });
+    }
+
+    #[test]
+    fn rejects_sizes_that_are_not_numbers() {
+        for size in ["100px", "100x", "x100", "-5", "1e3", "100x50x2", "99999999999", "１００", ""] {
+            assert_eq!(parse_size(size), None, "{:?}", size);
+        }
+    }
+
+    #[test]
+    fn renders_images() {
+        assert_eq!(render("![[a b.png]]"), r#"<img src="a b.png" alt="a b.png" />"#);
+        assert_eq!(render("![[a.png|100x50]]"), r#"<img src="a.png" width="100" height="50" alt="a.png" />"#);
+        assert_eq!(render("![[a.png|A cat|100]]"), r#"<img src="a.png" width="100" alt="A cat" />"#);
+    }
+
+    #[test]
+    fn escapes_attribute_injection_in_the_size() {
+        assert_eq!(
+            render(r#"![[x.png|100" onerror="alert(1)]]"#),
+            r#"<img src="x.png" alt="100&quot; onerror=&quot;alert(1)" />"#
+        );
+        assert_eq!(
+            render(r#"![[x.png|100x1" onload="alert(1)]]"#),
+            r#"<img src="x.png" alt="100x1&quot; onload=&quot;alert(1)" />"#
+        );
+    }
+
+    #[test]
+    fn escapes_the_path_and_alt_text() {
+        assert_eq!(
+            render(r#"![[x" onerror="alert(1).png]]"#),
+            r#"<img src="x&quot; onerror=&quot;alert(1).png" alt="x&quot; onerror=&quot;alert(1).png" />"#
+        );
+        assert_eq!(
+            render("![[x.png|'><script>alert(1)</script>]]"),
+            r#"<img src="x.png" alt="&#39;&gt;&lt;script&gt;alert(1)&lt;/script&gt;" />"#
+        );
+    }
+
+    #[test]
+    fn escapes_missing_note_targets() {
+        assert_eq!(
+            render_note("![[<script>alert(1)</script>]]"),
+            "<div class=\"markdown-embed markdown-embed-error\">Note not found: &lt;script&gt;alert(1)&lt;/script&gt;</div>\n"
+        );
+    }
+
+    #[test]
+    fn notes_embedded_inside_a_line_become_links() {
+        assert_eq!(
+            render_note("See ![[Note|the note]] here"),
+            "<p>See <a href=\"Note.md\" class=\"wikilink wikilink-unresolved\">the note</a> here</p>\n"
+        );
+        assert!(render_note("# A ![[Note]]").ends_with("A <a href=\"Note.md\" class=\"wikilink wikilink-unresolved\">Note</a></h1>\n"));
+    }
+
+    #[test]
+    fn notes_on_a_line_of_their_own_split_the_paragraph() {
+        assert_eq!(
+            render_note("Before\n![[Note]]\nAfter"),
+            "<p>Before</p>\n<div class=\"markdown-embed markdown-embed-error\">Note not found: Note</div>\n<p>After</p>\n"
+        );
+        assert_eq!(
+            render_note("> ![[Note]]"),
+            "<blockquote>\n<div class=\"markdown-embed markdown-embed-error\">Note not found: Note</div>\n</blockquote>\n"
+        );
+    }
+
+    #[test]
+    fn extracts_sections_up_to_the_next_heading_of_the_same_level() {
+        let note = "# Top\nintro\n## Set Up\nsteps\n### Detail\nmore\n## Next\nrest\n";
+        assert_eq!(extract_fragment(note, "set-up").as_deref(), Some("## Set Up\nsteps\n### Detail\nmore\n"));
+        assert_eq!(extract_fragment(note, "Next").as_deref(), Some("## Next\nrest\n"));
+        assert_eq!(extract_fragment(note, "Missing"), None);
+
+        let setext = "Title\n=====\ntext\n\nOther\n=====\n";
+        assert_eq!(extract_fragment(setext, "title").as_deref(), Some("Title\n=====\ntext\n\n"));
+    }
+
+    #[test]
+    fn ignores_heading_lines_in_code() {
+        let note = "## A\n~~~~\n```\n## B\n~~~~\n\n    ## C\n## D\n";
+        assert_eq!(extract_fragment(note, "A").as_deref(), Some("## A\n~~~~\n```\n## B\n~~~~\n\n    ## C\n"));
+        assert_eq!(extract_fragment(note, "B"), None);
+        assert_eq!(extract_fragment(note, "C"), None);
+    }
+}
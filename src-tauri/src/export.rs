@@ -0,0 +1,60 @@
+use base64::Engine;
+use std::fs;
+use std::path::Path;
+
+use crate::links;
+use crate::markdown::{self, escape_html, RenderOptions};
+
+const EXPORT_STYLE: &str = include_str!("export.css");
+
+/// Wraps rendered Markdown in a complete HTML document with the bundled stylesheet.
+pub fn standalone_html(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n<title>{}</title>\n<style>\n{}</style>\n</head>\n<body>\n<article class=\"markdown-body\">\n{}</article>\n</body>\n</html>\n",
+        escape_html(title),
+        EXPORT_STYLE,
+        body
+    )
+}
+
+/// Renders `content` into a single portable HTML file. Local images are
+/// resolved against `source`'s folder and embedded as `data:` URIs.
+pub fn export_document(content: &str, source: &Path, options: RenderOptions) -> String {
+    let title = source.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let base_dir = source.parent().unwrap_or_else(|| Path::new("."));
+
+    let body = markdown::convert_markdown(content, Some(source), options);
+    standalone_html(&title, &inline_local_images(&body, base_dir))
+}
+
+/// Replaces the `src` of every `<img>` that points at a readable local file
+/// with a `data:` URI. Remote, `data:` and unreadable sources are left as-is.
+pub fn inline_local_images(html: &str, base_dir: &Path) -> String {
+    links::map_img_srcs(html, |src| {
+        links::local_path(src, base_dir)
+            .filter(|path| path.is_file())
+            .and_then(|path| image_data_uri(&path))
+    })
+}
+
+fn image_data_uri(path: &Path) -> Option<String> {
+    let mime = image_mime_type(path)?;
+    let bytes = fs::read(path).ok()?;
+    Some(format!("data:{};base64,{}", mime, base64::engine::general_purpose::STANDARD.encode(bytes)))
+}
+
+fn image_mime_type(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_string_lossy().to_lowercase();
+    let mime = match ext.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "bmp" => "image/bmp",
+        "ico" => "image/x-icon",
+        "avif" => "image/avif",
+        _ => return None,
+    };
+    Some(mime)
+}
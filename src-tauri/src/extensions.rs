@@ -0,0 +1,242 @@
+use comrak::nodes::{Ast, AstNode, LineColumn, NodeValue};
+use comrak::Arena;
+use regex::{Captures, Regex};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::sync::LazyLock;
+
+use crate::dialect::DialectProfile;
+
+static MARK_DELIMITER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"=+").unwrap());
+static SHORTCODE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r":([A-Za-z0-9_+-]+):").unwrap());
+static SUBSCRIPT: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(^|[^~\\])~([^~\s/\\]+)~([^~]|$)").unwrap());
+
+/// Stand-ins for the tildes of a `~subscript~` from `protect_subscripts`
+/// until `apply`. Private-use characters are plain text to comrak, even in
+/// link destinations, and they can't clash with the source.
+const SUB_OPEN: char = '\u{E000}';
+const SUB_CLOSE: char = '\u{E001}';
+
+/// Pandoc's `H~2~O` subscripts: a single tilde on each side and no spaces
+/// in between, so `~~strikethrough~~` is left to comrak. comrak would take
+/// single tildes as strikethrough too, so they are swapped for stand-ins
+/// before parsing and turned into `<sub>` by `apply`.
+pub fn protect_subscripts(text: &str) -> Cow<'_, str> {
+    SUBSCRIPT.replace_all(text, |caps: &Captures| format!("{}{}{}{}{}", &caps[1], SUB_OPEN, &caps[2], SUB_CLOSE, &caps[3]))
+}
+
+/// The inline extensions that work on the parsed document: Obsidian's
+/// `==highlights==`, `~subscripts~` and the shortcodes comrak can't parse.
+/// Only text nodes change, so code, link destinations and raw HTML keep
+/// their characters.
+pub fn apply<'a>(arena: &'a Arena<AstNode<'a>>, root: &'a AstNode<'a>, profile: &DialectProfile) {
+    if profile.subscript {
+        restore_autolinks(root);
+    }
+
+    let parents: Vec<_> = root
+        .descendants()
+        .filter(|node| node.children().any(|child| matches!(child.data.borrow().value, NodeValue::Text(_))))
+        .collect();
+    for parent in parents {
+        if profile.mark {
+            wrap(arena, mark_pairs(parent), "mark");
+        }
+        if profile.subscript {
+            wrap(arena, subscript_pairs(parent), "sub");
+        }
+    }
+
+    for node in root.descendants() {
+        let mut ast = node.data.borrow_mut();
+        match &mut ast.value {
+            NodeValue::Text(text) => {
+                if profile.subscript {
+                    restore_tildes(text);
+                }
+                if profile.emoji {
+                    if let Cow::Owned(replaced) = emoji_aliases(text) {
+                        *text = replaced;
+                    }
+                }
+            }
+            NodeValue::HtmlInline(html) if profile.subscript => restore_tildes(html),
+            NodeValue::HtmlBlock(block) if profile.subscript => restore_tildes(&mut block.literal),
+            NodeValue::Link(link) | NodeValue::Image(link) if profile.subscript => {
+                restore_tildes(&mut link.url);
+                restore_tildes(&mut link.title);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A delimiter in the text of `node`, at bytes `start..end`.
+struct Delimiter<'a> {
+    node: &'a AstNode<'a>,
+    start: usize,
+    end: usize,
+}
+
+/// `==text==` among the children of `parent`, as in Obsidian: no space just
+/// inside the markers and no line break in between. The text may hold other
+/// formatting, as long as both markers are in text at the same level.
+fn mark_pairs<'a>(parent: &'a AstNode<'a>) -> Vec<(Delimiter<'a>, Delimiter<'a>)> {
+    let mut pairs = Vec::new();
+    let mut open: Option<Delimiter<'a>> = None;
+
+    for child in parent.children() {
+        let ast = child.data.borrow();
+        let text = match &ast.value {
+            NodeValue::Text(text) => text,
+            NodeValue::SoftBreak | NodeValue::LineBreak => {
+                open = None;
+                continue;
+            }
+            _ => continue,
+        };
+        for m in MARK_DELIMITER.find_iter(text).filter(|m| m.len() == 2) {
+            let can_open = text[m.end()..].chars().next().map_or(child.next_sibling().is_some(), |c| !c.is_whitespace());
+            let can_close = text[..m.start()].chars().next_back().map_or(child.previous_sibling().is_some(), |c| !c.is_whitespace());
+            let here = Delimiter { node: child, start: m.start(), end: m.end() };
+            match open.take() {
+                Some(opener) if can_close => pairs.push((opener, here)),
+                opener => open = if can_open { Some(here) } else { opener },
+            }
+        }
+    }
+    pairs
+}
+
+fn subscript_pairs<'a>(parent: &'a AstNode<'a>) -> Vec<(Delimiter<'a>, Delimiter<'a>)> {
+    let mut pairs = Vec::new();
+    let mut open: Option<Delimiter<'a>> = None;
+
+    for child in parent.children() {
+        let ast = child.data.borrow();
+        let NodeValue::Text(text) = &ast.value else { continue };
+        for (start, c) in text.char_indices() {
+            let here = Delimiter { node: child, start, end: start + c.len_utf8() };
+            match c {
+                SUB_OPEN => open = Some(here),
+                SUB_CLOSE => {
+                    if let Some(opener) = open.take() {
+                        pairs.push((opener, here));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    pairs
+}
+
+/// Replaces each pair of delimiters with `<tag>` and `</tag>` as inline
+/// HTML. Works backwards, so the text before a delimiter is still in the
+/// node it was found in.
+fn wrap<'a>(arena: &'a Arena<AstNode<'a>>, pairs: Vec<(Delimiter<'a>, Delimiter<'a>)>, tag: &str) {
+    for (open, close) in pairs.into_iter().rev() {
+        replace_delimiter(arena, close, format!("</{}>", tag));
+        replace_delimiter(arena, open, format!("<{}>", tag));
+    }
+}
+
+fn replace_delimiter<'a>(arena: &'a Arena<AstNode<'a>>, delimiter: Delimiter<'a>, html: String) {
+    let (rest, start) = {
+        let mut ast = delimiter.node.data.borrow_mut();
+        let start = ast.sourcepos.start;
+        let NodeValue::Text(text) = &mut ast.value else { return };
+        let rest = text.split_off(delimiter.end);
+        text.truncate(delimiter.start);
+        (rest, start)
+    };
+    let tag = new_node(arena, NodeValue::HtmlInline(html), start);
+    delimiter.node.insert_after(tag);
+    if !rest.is_empty() {
+        tag.insert_after(new_node(arena, NodeValue::Text(rest), start));
+    }
+}
+
+fn new_node<'a>(arena: &'a Arena<AstNode<'a>>, value: NodeValue, start: LineColumn) -> &'a AstNode<'a> {
+    arena.alloc(AstNode::new(RefCell::new(Ast::new(value, start))))
+}
+
+/// A bare URL with tildes in it is one link, not a subscript.
+fn restore_autolinks<'a>(root: &'a AstNode<'a>) {
+    for node in root.descendants() {
+        let is_protected = matches!(&node.data.borrow().value, NodeValue::Link(link) if link.url.contains([SUB_OPEN, SUB_CLOSE]));
+        if !is_protected {
+            continue;
+        }
+        for descendant in node.descendants() {
+            match &mut descendant.data.borrow_mut().value {
+                NodeValue::Link(link) => restore_tildes(&mut link.url),
+                NodeValue::Text(text) => restore_tildes(text),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn restore_tildes(text: &mut String) {
+    if text.contains([SUB_OPEN, SUB_CLOSE]) {
+        *text = text.replace([SUB_OPEN, SUB_CLOSE], "~");
+    }
+}
+
+/// Shortcodes comrak can't parse because they contain digits or a plus,
+/// like `:+1:` and `:100:`, replaced with their emoji. The others are left
+/// to comrak.
+fn emoji_aliases(text: &str) -> Cow<'_, str> {
+    SHORTCODE.replace_all(text, |caps: &Captures| {
+        let name = &caps[1];
+        let plain = name.bytes().all(|b| b.is_ascii_alphabetic() || b == b'_' || b == b'-');
+        match emojis::get_by_shortcode(name) {
+            Some(emoji) if !plain => emoji.as_str().to_string(),
+            _ => caps[0].to_string(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dialect::Dialect;
+    use crate::markdown::{convert_markdown, RenderOptions};
+
+    fn render(content: &str, options: RenderOptions) -> String {
+        let html = convert_markdown(content, None, options);
+        regex::Regex::new(r#" data-sourcepos="[^"]*""#).unwrap().replace_all(&html, "").into_owned()
+    }
+
+    fn obsidian() -> RenderOptions {
+        RenderOptions { dialect: Dialect::Obsidian, ..RenderOptions::default() }
+    }
+
+    #[test]
+    fn highlights_keep_formatting_and_skip_link_destinations() {
+        let html = render("==a *b* c== and [x](https://example.com/a==b==c)\n", obsidian());
+        assert!(html.contains("<mark>a <em>b</em> c</mark>"), "{}", html);
+        assert!(html.contains("href=\"https://example.com/a==b==c\""), "{}", html);
+
+        // No space just inside the markers
+        assert!(!render("a == b == c\n", obsidian()).contains("<mark>"));
+    }
+
+    #[test]
+    fn highlights_work_in_wiki_link_labels() {
+        let html = render("[[Note|a ==b== c]]\n", obsidian());
+        assert!(html.contains("a <mark>b</mark> c</a>"), "{}", html);
+        assert!(!html.contains("&lt;mark"), "{}", html);
+    }
+
+    #[test]
+    fn subscripts_skip_link_destinations() {
+        let options = RenderOptions { subscript: true, ..RenderOptions::default() };
+        let html = render("H~2~O [x](https://example.com/a~b~c) https://example.com/d~e~f\n", options);
+        assert!(html.contains("H<sub>2</sub>O"), "{}", html);
+        assert!(html.contains("href=\"https://example.com/a~b~c\""), "{}", html);
+        assert!(html.contains("href=\"https://example.com/d~e~f\""), "{}", html);
+        assert!(html.contains(">https://example.com/d~e~f</a>"), "{}", html);
+        assert!(!html.contains('\u{E000}') && !html.contains("%EE%80"), "{}", html);
+    }
+}
@@ -0,0 +1,720 @@
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{ErrorKind, Read, Write};
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+use crate::settings;
+
+/// Timestamped backups kept per file; older ones are deleted.
+const KEEP_BACKUPS: usize = 20;
+
+/// How much of a file is looked at to spot UTF-16 without a byte order mark.
+const UTF16_SNIFF_BYTES: usize = 4096;
+
+/// A text file decoded for editing, with what's needed to write it back the
+/// way it was.
+#[derive(Serialize, Clone, Debug)]
+pub struct TextFile {
+    /// The text, with `\n` line breaks whatever the file uses.
+    pub content: String,
+    /// WHATWG name of the encoding, such as `UTF-8`, `UTF-16LE` or
+    /// `windows-1252`.
+    pub encoding: String,
+    /// Whether the file starts with a byte order mark.
+    pub bom: bool,
+    /// The line breaks most of the file uses.
+    pub line_ending: LineEnding,
+    /// Whether some lines end differently, which saving will even out.
+    pub mixed_line_endings: bool,
+    /// The file as it was read, to notice if it changes before it's saved.
+    pub stamp: FileStamp,
+}
+
+/// What a file on disk looked like at some point.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug, Default)]
+pub struct FileStamp {
+    /// Last modification, in milliseconds since the Unix epoch.
+    pub modified: u64,
+    pub size: u64,
+    /// Hash of the file's bytes, in hex.
+    pub hash: String,
+}
+
+impl FileStamp {
+    fn new(metadata: &Metadata, bytes: &[u8]) -> FileStamp {
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+            .map_or(0, |since| since.as_millis() as u64);
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        FileStamp { modified, size: metadata.len(), hash: format!("{:016x}", hasher.finish()) }
+    }
+}
+
+/// Why a save didn't happen.
+#[derive(Serialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SaveError {
+    /// The file changed on disk since it was read. `on_disk` is what it
+    /// holds now.
+    Conflict { on_disk: TextFile },
+    Failed { message: String },
+}
+
+impl From<String> for SaveError {
+    fn from(message: String) -> Self {
+        SaveError::Failed { message }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    /// What the platform's own tools write.
+    pub fn native() -> LineEnding {
+        if cfg!(windows) { LineEnding::Crlf } else { LineEnding::Lf }
+    }
+
+    /// The style most lines of `text` end in, and whether any end otherwise.
+    /// A lone `\r` always counts as otherwise, since it can't be written
+    /// back. Text without line breaks gets the native style.
+    pub fn detect(text: &str) -> (LineEnding, bool) {
+        let bytes = text.as_bytes();
+        let (mut lf, mut crlf, mut cr) = (0, 0, 0);
+        for (i, byte) in bytes.iter().enumerate() {
+            match byte {
+                b'\n' if i > 0 && bytes[i - 1] == b'\r' => crlf += 1,
+                b'\n' => lf += 1,
+                b'\r' if bytes.get(i + 1) != Some(&b'\n') => cr += 1,
+                _ => {}
+            }
+        }
+        let ending = match (lf, crlf) {
+            (0, 0) => LineEnding::native(),
+            _ if crlf > lf => LineEnding::Crlf,
+            _ => LineEnding::Lf,
+        };
+        (ending, (lf > 0 && crlf > 0) || cr > 0)
+    }
+
+    /// `text` with every line break, of whatever style, turned into this one.
+    pub fn apply(self, text: &str) -> String {
+        let text = normalize_line_endings(text);
+        match self {
+            LineEnding::Lf => text.into_owned(),
+            LineEnding::Crlf => text.replace('\n', "\r\n"),
+        }
+    }
+}
+
+/// `text` with `\r\n` and lone `\r` line breaks turned into `\n`.
+pub fn normalize_line_endings(text: &str) -> Cow<'_, str> {
+    if !text.contains('\r') {
+        return Cow::Borrowed(text);
+    }
+    Cow::Owned(text.replace("\r\n", "\n").replace('\r', "\n"))
+}
+
+/// What to keep of a file's previous contents when it is saved over.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Backups {
+    #[default]
+    Off,
+    /// `notes.md.bak` next to the file, replaced on every save.
+    Rolling,
+    /// Dated copies in the app's data folder.
+    Timestamped,
+}
+
+pub const ALL_BACKUPS: [Backups; 3] = [Backups::Off, Backups::Rolling, Backups::Timestamped];
+
+impl Backups {
+    pub fn id(self) -> &'static str {
+        match self {
+            Backups::Off => "off",
+            Backups::Rolling => "rolling",
+            Backups::Timestamped => "timestamped",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Backups> {
+        ALL_BACKUPS.into_iter().find(|b| b.id() == id)
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Backups::Off => "Off",
+            Backups::Rolling => "Keep a .bak File",
+            Backups::Timestamped => "Keep Timestamped Copies",
+        }
+    }
+}
+
+/// Saves `bytes` to `path` so that the file holds either the old or the new
+/// contents, never a mix: they go to a temporary file in the same folder,
+/// which is flushed to disk and then renamed over the original. The
+/// original's permissions are kept, and a symlink is saved through rather
+/// than replaced.
+///
+/// A file with other hard links, or in a folder where no temporary file can
+/// be created, is overwritten in place instead: a rename would split it from
+/// its other names, and there is nowhere else to write to.
+pub fn save(path: &Path, bytes: &[u8], backups: Backups) -> Result<(), String> {
+    let target = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let existing = fs::metadata(&target).ok();
+    if existing.as_ref().is_some_and(|m| m.permissions().readonly()) {
+        return Err(format!("{} is read-only", path.display()));
+    }
+
+    if existing.is_some() {
+        back_up(&target, backups).map_err(|e| format!("failed to back up {}: {}", path.display(), e))?;
+    }
+
+    let dir = target.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let temp = match &existing {
+        Some(metadata) if link_count(metadata) > 1 => None,
+        _ => create_temp(dir, &target).ok(),
+    };
+    let Some((temp_path, mut temp)) = temp else {
+        return write_in_place(&target, bytes).map_err(|e| format!("failed to save {}: {}", path.display(), e));
+    };
+
+    let written = (|| {
+        temp.write_all(bytes)?;
+        if let Some(metadata) = &existing {
+            temp.set_permissions(metadata.permissions())?;
+        }
+        temp.sync_all()?;
+        drop(temp);
+        fs::rename(&temp_path, &target)?;
+        sync_dir(dir)
+    })();
+
+    written.map_err(|e| {
+        let _ = fs::remove_file(&temp_path);
+        format!("failed to save {}: {}", path.display(), e)
+    })
+}
+
+/// Truncates `path` and writes `bytes` to it, keeping the file itself.
+fn write_in_place(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().write(true).create(true).truncate(true).open(path)?;
+    file.write_all(bytes)?;
+    file.sync_all()
+}
+
+/// How many names the file has. Only Unix says; elsewhere it counts as one.
+fn link_count(metadata: &Metadata) -> u64 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        metadata.nlink()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        1
+    }
+}
+
+/// Reads and decodes a text file. The encoding comes from its byte order
+/// mark, or failing that is guessed from the bytes, unless `encoding` says
+/// what it is.
+pub fn read(path: &Path, encoding: Option<&'static Encoding>) -> std::io::Result<TextFile> {
+    let (bytes, stamp) = read_stamped(path)?;
+    Ok(TextFile { stamp, ..decode(&bytes, encoding) })
+}
+
+/// How `path` looks now, or `None` if there is no such file.
+pub fn stamp(path: &Path) -> std::io::Result<Option<FileStamp>> {
+    match read_stamped(path) {
+        Ok((_, stamp)) => Ok(Some(stamp)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn read_stamped(path: &Path) -> std::io::Result<(Vec<u8>, FileStamp)> {
+    let mut file = File::open(path)?;
+    let metadata = file.metadata()?;
+    let mut bytes = Vec::with_capacity(metadata.len() as usize);
+    file.read_to_end(&mut bytes)?;
+    let stamp = FileStamp::new(&metadata, &bytes);
+    Ok((bytes, stamp))
+}
+
+/// Fails with `SaveError::Conflict` if the contents of `path` are no longer
+/// those `expected` was taken from. A new timestamp alone, as after a
+/// `touch` or checking out the same version, doesn't count, and neither
+/// does the file having been deleted, since saving loses nothing then.
+pub fn check_unchanged(path: &Path, expected: &FileStamp) -> Result<(), SaveError> {
+    let Some(current) = stamp(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))? else {
+        return Ok(());
+    };
+    if current.size == expected.size && current.hash == expected.hash {
+        return Ok(());
+    }
+    let on_disk = read(path, None).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    Err(SaveError::Conflict { on_disk })
+}
+
+/// Like `fs::read_to_string`, but for text in any encoding `read` detects,
+/// and with `\n` line breaks.
+pub fn read_to_string(path: &Path) -> std::io::Result<String> {
+    read(path, None).map(|file| file.content)
+}
+
+/// The encoding an encoding name (or any of its WHATWG labels) refers to.
+pub fn encoding_for_label(label: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(label.trim().as_bytes()).ok_or_else(|| format!("Unknown encoding '{}'", label))
+}
+
+/// Decodes file contents as `read` does, leaving the stamp empty.
+pub fn decode(bytes: &[u8], encoding: Option<&'static Encoding>) -> TextFile {
+    let (encoding, bom_length) = match Encoding::for_bom(bytes) {
+        Some((found, length)) if encoding.is_none_or(|e| e == found) => (found, length),
+        _ => (encoding.unwrap_or_else(|| detect(bytes)), 0),
+    };
+    let (content, _) = encoding.decode_without_bom_handling(&bytes[bom_length..]);
+    let (line_ending, mixed_line_endings) = LineEnding::detect(&content);
+    TextFile {
+        content: normalize_line_endings(&content).into_owned(),
+        encoding: encoding.name().to_string(),
+        bom: bom_length > 0,
+        line_ending,
+        mixed_line_endings,
+        stamp: FileStamp::default(),
+    }
+}
+
+/// `content` as bytes in `encoding`, with a byte order mark first if `bom`
+/// is set and the encoding has one. Fails rather than lose characters the
+/// encoding can't represent.
+pub fn encode(content: &str, encoding: &'static Encoding, bom: bool) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::with_capacity(content.len() + 3);
+    if encoding == UTF_16LE || encoding == UTF_16BE {
+        let little_endian = encoding == UTF_16LE;
+        let unit = |u: u16| if little_endian { u.to_le_bytes() } else { u.to_be_bytes() };
+        if bom {
+            bytes.extend(unit(0xFEFF));
+        }
+        for u in content.encode_utf16() {
+            bytes.extend(unit(u));
+        }
+        return Ok(bytes);
+    }
+
+    if encoding == UTF_8 {
+        if bom {
+            bytes.extend([0xEF, 0xBB, 0xBF]);
+        }
+        bytes.extend(content.as_bytes());
+        return Ok(bytes);
+    }
+
+    let (encoded, _, unmappable) = encoding.encode(content);
+    if unmappable {
+        let mut buffer = [0; 4];
+        let first = content.chars().find(|c| encoding.encode(c.encode_utf8(&mut buffer)).2).unwrap_or('?');
+        return Err(format!("'{}' can't be saved as {}; pick an encoding such as UTF-8 that can hold it", first, encoding.name()));
+    }
+    Ok(encoded.into_owned())
+}
+
+/// Guesses the encoding of text without a byte order mark: UTF-16 if the
+/// zero bytes of ASCII characters line up, UTF-8 if the bytes are valid
+/// UTF-8, and otherwise the likeliest legacy code page.
+fn detect(bytes: &[u8]) -> &'static Encoding {
+    if let Some(encoding) = detect_utf16(&bytes[..bytes.len().min(UTF16_SNIFF_BYTES)]) {
+        return encoding;
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return UTF_8;
+    }
+    let mut detector = EncodingDetector::new();
+    detector.feed(bytes, true);
+    detector.guess(None, true)
+}
+
+fn detect_utf16(sample: &[u8]) -> Option<&'static Encoding> {
+    // The sample is a whole number of units unless the file is odd-sized
+    let units = sample.len() / 2;
+    if units == 0 || !sample.len().is_multiple_of(2) {
+        return None;
+    }
+    let zeros = |offset: usize| sample.chunks_exact(2).filter(|unit| unit[offset] == 0).count();
+    let (even, odd) = (zeros(0), zeros(1));
+    // Mostly ASCII text has a zero in every other byte and none in between
+    if odd * 10 > units * 4 && even * 20 < units {
+        Some(UTF_16LE)
+    } else if even * 10 > units * 4 && odd * 20 < units {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+
+/// Where timestamped backups go, one folder per file.
+pub fn backups_dir() -> Option<PathBuf> {
+    settings::project_dirs().map(|dirs| dirs.data_dir().join("backups"))
+}
+
+/// A new, empty file next to `target` that nothing else is using.
+fn create_temp(dir: &Path, target: &Path) -> std::io::Result<(PathBuf, File)> {
+    let name = target.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let mut attempt = 0;
+    loop {
+        let path = dir.join(format!(".{}.{}-{}.tmp", name, std::process::id(), attempt));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => attempt += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Makes the rename itself durable. Windows has no way to sync a folder and
+/// doesn't need it.
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    if cfg!(unix) {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+fn back_up(path: &Path, backups: Backups) -> std::io::Result<()> {
+    match backups {
+        Backups::Off => Ok(()),
+        Backups::Rolling => {
+            let mut backup = path.as_os_str().to_owned();
+            backup.push(".bak");
+            fs::copy(path, backup).map(|_| ())
+        }
+        Backups::Timestamped => {
+            let dir = backups_dir().ok_or_else(|| std::io::Error::new(ErrorKind::NotFound, "no data folder available"))?.join(backup_folder(path));
+            fs::create_dir_all(&dir)?;
+            copy_timestamped(path, &dir)?;
+            prune(&dir)
+        }
+    }
+}
+
+/// Copies `path` into `dir` as `2024-05-01_14-03-09-250.md`. Saves within
+/// the same millisecond get `_1`, `_2`... after the time, which keeps the
+/// names sorting by age.
+fn copy_timestamped(path: &Path, dir: &Path) -> std::io::Result<PathBuf> {
+    let stamp = chrono::Local::now().format("%Y-%m-%d_%H-%M-%S-%3f").to_string();
+    let ext = path.extension().map(|ext| format!(".{}", ext.to_string_lossy())).unwrap_or_default();
+    let mut source = File::open(path)?;
+
+    let mut attempt = 0;
+    let (backup_path, mut backup) = loop {
+        let name = match attempt {
+            0 => format!("{}{}", stamp, ext),
+            n => format!("{}_{}{}", stamp, n, ext),
+        };
+        let backup_path = dir.join(name);
+        match OpenOptions::new().write(true).create_new(true).open(&backup_path) {
+            Ok(file) => break (backup_path, file),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => attempt += 1,
+            Err(e) => return Err(e),
+        }
+    };
+    std::io::copy(&mut source, &mut backup)?;
+    Ok(backup_path)
+}
+
+/// `notes-1a2b3c4d5e6f7a8b`: the file's name, to find it by, and a hash of
+/// its full path, to tell apart files with the same name. The hash is
+/// 64-bit FNV-1a, which unlike `DefaultHasher` is the same in every build,
+/// so a file's backups stay in one folder across updates.
+fn backup_folder(path: &Path) -> String {
+    let hash = path.as_os_str().as_encoded_bytes().iter().fold(0xcbf29ce484222325u64, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    format!("{}-{:016x}", stem, hash)
+}
+
+/// Deletes all but the newest backups. Their names sort by date.
+fn prune(dir: &Path) -> std::io::Result<()> {
+    let mut backups: Vec<PathBuf> = fs::read_dir(dir)?.filter_map(|entry| entry.ok().map(|e| e.path())).collect();
+    backups.sort();
+    let excess = backups.len().saturating_sub(KEEP_BACKUPS);
+    for old in &backups[..excess] {
+        fs::remove_file(old)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn timestamped_backups_made_together_get_their_own_names() {
+        let temp = TempDir::new();
+        let dir = temp.path();
+        let note = dir.join("note.md");
+        let backups = dir.join("backups");
+        fs::create_dir(&backups).unwrap();
+
+        let mut names = Vec::new();
+        for i in 0..5 {
+            fs::write(&note, format!("version {}", i)).unwrap();
+            names.push(copy_timestamped(&note, &backups).unwrap());
+        }
+
+        let mut sorted = names.clone();
+        sorted.sort();
+        assert_eq!(sorted, names);
+        for (i, name) in names.iter().enumerate() {
+            assert_eq!(fs::read_to_string(name).unwrap(), format!("version {}", i));
+            assert_eq!(name.extension().unwrap(), "md");
+        }
+    }
+
+    #[test]
+    fn pruning_keeps_the_newest_backups() {
+        let temp = TempDir::new();
+        let dir = temp.path();
+        for i in 0..KEEP_BACKUPS + 3 {
+            fs::write(dir.join(format!("2024-01-01_00-00-00-{:03}.md", i)), "").unwrap();
+        }
+        prune(dir).unwrap();
+
+        let mut left: Vec<_> = fs::read_dir(dir).unwrap().map(|e| e.unwrap().file_name().into_string().unwrap()).collect();
+        left.sort();
+        assert_eq!(left.len(), KEEP_BACKUPS);
+        assert_eq!(left[0], "2024-01-01_00-00-00-003.md");
+    }
+
+    #[test]
+    fn saves_new_and_existing_files_without_leaving_temp_files() {
+        let temp = TempDir::new();
+        let dir = temp.path();
+        let note = dir.join("note.md");
+        save(&note, b"first", Backups::Off).unwrap();
+        assert_eq!(fs::read(&note).unwrap(), b"first");
+        save(&note, b"second", Backups::Off).unwrap();
+        assert_eq!(fs::read(&note).unwrap(), b"second");
+
+        let names: Vec<_> = fs::read_dir(dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+        assert_eq!(names, ["note.md"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hard_linked_files_are_saved_in_place() {
+        let temp = TempDir::new();
+        let dir = temp.path();
+        let note = dir.join("note.md");
+        let other = dir.join("other.md");
+        fs::write(&note, "old").unwrap();
+        fs::hard_link(&note, &other).unwrap();
+
+        save(&note, b"new", Backups::Off).unwrap();
+        assert_eq!(fs::read(&other).unwrap(), b"new");
+    }
+
+    #[test]
+    fn backup_folders_are_stable() {
+        assert_eq!(backup_folder(Path::new("/notes/todo.md")), backup_folder(Path::new("/notes/todo.md")));
+        assert_ne!(backup_folder(Path::new("/notes/todo.md")), backup_folder(Path::new("/work/todo.md")));
+        assert_eq!(backup_folder(Path::new("")), "-cbf29ce484222325");
+    }
+
+    #[test]
+    fn rolling_backups_hold_the_previous_version() {
+        let temp = TempDir::new();
+        let dir = temp.path();
+        let note = dir.join("note.md");
+        save(&note, b"one", Backups::Rolling).unwrap();
+        assert!(!dir.join("note.md.bak").exists());
+        save(&note, b"two", Backups::Rolling).unwrap();
+        save(&note, b"three", Backups::Rolling).unwrap();
+        assert_eq!(fs::read(dir.join("note.md.bak")).unwrap(), b"two");
+        assert_eq!(fs::read(&note).unwrap(), b"three");
+    }
+
+    #[test]
+    fn refuses_to_save_over_read_only_files() {
+        let temp = TempDir::new();
+        let dir = temp.path();
+        let note = dir.join("note.md");
+        fs::write(&note, "kept").unwrap();
+        let mut permissions = fs::metadata(&note).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&note, permissions.clone()).unwrap();
+
+        assert!(save(&note, b"lost", Backups::Off).unwrap_err().contains("read-only"));
+        assert_eq!(fs::read(&note).unwrap(), b"kept");
+
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false);
+        fs::set_permissions(&note, permissions).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_permissions_and_saves_through_symlinks() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let temp = TempDir::new();
+        let dir = temp.path();
+        let note = dir.join("note.md");
+        fs::write(&note, "old").unwrap();
+        fs::set_permissions(&note, fs::Permissions::from_mode(0o640)).unwrap();
+        let link = dir.join("link.md");
+        symlink(&note, &link).unwrap();
+
+        save(&link, b"new", Backups::Off).unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read(&note).unwrap(), b"new");
+        assert_eq!(fs::metadata(&note).unwrap().permissions().mode() & 0o777, 0o640);
+    }
+
+    #[test]
+    fn encodings_round_trip() {
+        let text = "Grüße, naïve café — 日本語\n";
+        for (label, bom) in [("UTF-8", false), ("UTF-8", true), ("UTF-16LE", false), ("UTF-16LE", true), ("UTF-16BE", false), ("UTF-16BE", true)] {
+            let encoding = encoding_for_label(label).unwrap();
+            let bytes = encode(text, encoding, bom).unwrap();
+            let file = decode(&bytes, None);
+            assert_eq!(file.content, text, "{} bom={}", label, bom);
+            assert_eq!(file.encoding, label, "{} bom={}", label, bom);
+            assert_eq!(file.bom, bom, "{} bom={}", label, bom);
+        }
+
+        for (label, text) in [("windows-1252", "Grüße, naïve café\n"), ("Shift_JIS", "日本語のテキストです。\n")] {
+            let encoding = encoding_for_label(label).unwrap();
+            let bytes = encode(text, encoding, false).unwrap();
+            assert_eq!(decode(&bytes, Some(encoding)).content, text);
+        }
+    }
+
+    #[test]
+    fn detects_encodings_without_a_bom() {
+        assert_eq!(decode("plain ascii".as_bytes(), None).encoding, "UTF-8");
+        assert_eq!(decode("naïve".as_bytes(), None).encoding, "UTF-8");
+        let latin = encode("Ça coûte très cher, à peine croyable, déjà vu.\n", encoding_for_label("windows-1252").unwrap(), false).unwrap();
+        assert_eq!(decode(&latin, None).encoding, "windows-1252");
+    }
+
+    #[test]
+    fn an_explicit_encoding_overrides_detection() {
+        let bytes = "café".as_bytes();
+        let file = decode(bytes, Some(encoding_for_label("latin1").unwrap()));
+        assert_eq!(file.encoding, "windows-1252");
+        assert_eq!(file.content, "cafÃ©");
+    }
+
+    #[test]
+    fn refuses_characters_the_encoding_cannot_hold() {
+        let error = encode("price: 5 €, 日本", encoding_for_label("ISO-8859-2").unwrap(), false).unwrap_err();
+        assert!(error.contains("'€'"), "{}", error);
+    }
+
+    #[test]
+    fn reads_files_in_their_encoding() {
+        let temp = TempDir::new();
+        let dir = temp.path();
+        let note = dir.join("note.md");
+        fs::write(&note, encode("Grüße\n", UTF_16LE, true).unwrap()).unwrap();
+        let file = read(&note, None).unwrap();
+        assert_eq!((file.content.as_str(), file.encoding.as_str(), file.bom), ("Grüße\n", "UTF-16LE", true));
+        assert_eq!(read_to_string(&note).unwrap(), "Grüße\n");
+    }
+
+    #[test]
+    fn detects_line_endings() {
+        assert_eq!(LineEnding::detect("a\nb\n"), (LineEnding::Lf, false));
+        assert_eq!(LineEnding::detect("a\r\nb\r\n"), (LineEnding::Crlf, false));
+        assert_eq!(LineEnding::detect("a\r\nb\r\nc\n"), (LineEnding::Crlf, true));
+        assert_eq!(LineEnding::detect("a\nb\nc\r\n"), (LineEnding::Lf, true));
+        assert_eq!(LineEnding::detect("a\rb\n"), (LineEnding::Lf, true));
+        assert_eq!(LineEnding::detect("one line"), (LineEnding::native(), false));
+    }
+
+    #[test]
+    fn applies_one_line_ending_throughout() {
+        let mixed = "a\r\nb\nc\rd";
+        assert_eq!(normalize_line_endings(mixed), "a\nb\nc\nd");
+        assert_eq!(LineEnding::Lf.apply(mixed), "a\nb\nc\nd");
+        assert_eq!(LineEnding::Crlf.apply(mixed), "a\r\nb\r\nc\r\nd");
+        assert!(matches!(normalize_line_endings("a\nb"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn decoding_normalizes_line_endings_and_remembers_them() {
+        let file = decode(b"a\r\nb\r\n", None);
+        assert_eq!(file.content, "a\nb\n");
+        assert_eq!((file.line_ending, file.mixed_line_endings), (LineEnding::Crlf, false));
+
+        let utf16 = encode(&LineEnding::Crlf.apply("a\nb\n"), UTF_16BE, false).unwrap();
+        let file = decode(&utf16, None);
+        assert_eq!(file.content, "a\nb\n");
+        assert_eq!(file.line_ending, LineEnding::Crlf);
+    }
+
+    #[test]
+    fn saving_over_an_unchanged_file_is_allowed() {
+        let temp = TempDir::new();
+        let dir = temp.path();
+        let note = dir.join("note.md");
+        fs::write(&note, "same").unwrap();
+        let expected = read(&note, None).unwrap().stamp;
+
+        // Rewriting the same bytes only moves the timestamp
+        fs::write(&note, "same").unwrap();
+        assert!(check_unchanged(&note, &expected).is_ok());
+
+        // Nothing is lost by saving over a deleted file
+        fs::remove_file(&note).unwrap();
+        assert!(check_unchanged(&note, &expected).is_ok());
+        assert_eq!(stamp(&note).unwrap(), None);
+    }
+
+    #[test]
+    fn changes_on_disk_are_a_conflict() {
+        let temp = TempDir::new();
+        let dir = temp.path();
+        let note = dir.join("note.md");
+        fs::write(&note, "mine").unwrap();
+        let expected = read(&note, None).unwrap().stamp;
+
+        fs::write(&note, "theirs\r\n").unwrap();
+        match check_unchanged(&note, &expected) {
+            Err(SaveError::Conflict { on_disk }) => {
+                assert_eq!(on_disk.content, "theirs\n");
+                assert_eq!(on_disk.line_ending, LineEnding::Crlf);
+                assert_eq!(Some(on_disk.stamp), stamp(&note).unwrap());
+            }
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+
+        // Same size, different bytes
+        fs::write(&note, "mime").unwrap();
+        assert!(matches!(check_unchanged(&note, &expected), Err(SaveError::Conflict { .. })));
+    }
+
+    #[test]
+    fn save_errors_serialize_with_their_kind() {
+        let json = serde_json::to_value(SaveError::from("disk full".to_string())).unwrap();
+        assert_eq!(json, serde_json::json!({ "kind": "failed", "message": "disk full" }));
+    }
+}
@@ -0,0 +1,154 @@
+use serde::Serialize;
+use serde_json::Value;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum FrontMatterFormat {
+    Yaml,
+    Toml,
+}
+
+/// Metadata block found at the top of a document.
+#[derive(Serialize, Clone, Debug)]
+pub struct FrontMatter {
+    pub format: FrontMatterFormat,
+    /// The text between the delimiters.
+    pub raw: String,
+    /// The parsed block, or `None` if it isn't valid YAML/TOML.
+    pub data: Option<Value>,
+    pub error: Option<String>,
+}
+
+/// Splits a leading YAML (`---`) or TOML (`+++`) front matter block off
+/// `content`. The block is replaced by the same number of empty lines, to
+/// keep the line count like the passes in `markdown::preprocess`.
+pub fn extract(content: &str) -> (Option<FrontMatter>, String) {
+    let body = content.strip_prefix('\u{feff}').unwrap_or(content);
+
+    let mut lines = body.split_inclusive('\n');
+    let Some(first) = lines.next() else {
+        return (None, content.to_string());
+    };
+    let (format, closers): (FrontMatterFormat, &[&str]) = match first.trim_end() {
+        "---" => (FrontMatterFormat::Yaml, &["---", "..."]),
+        "+++" => (FrontMatterFormat::Toml, &["+++"]),
+        _ => return (None, content.to_string()),
+    };
+
+    let mut raw = String::new();
+    let mut consumed = first.len();
+    let mut line_count = 1;
+    let mut closed = false;
+
+    for line in lines {
+        consumed += line.len();
+        line_count += 1;
+        if closers.contains(&line.trim_end()) {
+            closed = true;
+            break;
+        }
+        raw.push_str(line);
+    }
+
+    if !closed {
+        return (None, content.to_string());
+    }
+
+    let (data, error) = match parse(format, &raw) {
+        Ok(data) => (Some(data), None),
+        Err(e) => (None, Some(e)),
+    };
+
+    let rest = format!("{}{}", "\n".repeat(line_count), &body[consumed..]);
+    (Some(FrontMatter { format, raw, data, error }), rest)
+}
+
+fn parse(format: FrontMatterFormat, raw: &str) -> Result<Value, String> {
+    match format {
+        FrontMatterFormat::Yaml => {
+            // Only keys and values make metadata; a block of comments is none
+            match serde_yaml::from_str::<Value>(raw).map_err(|e| e.to_string())? {
+                Value::Null => Ok(Value::Object(Default::default())),
+                Value::Object(map) => Ok(Value::Object(map)),
+                _ => Err("front matter must be a mapping of keys to values".to_string()),
+            }
+        }
+        FrontMatterFormat::Toml => raw.parse::<toml::Table>()
+            .map(|table| toml_to_json(toml::Value::Table(table)))
+            .map_err(|e| e.to_string()),
+    }
+}
+
+fn toml_to_json(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(i) => Value::from(i),
+        toml::Value::Float(f) => Value::from(f),
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(d) => Value::String(d.to_string()),
+        toml::Value::Array(items) => Value::Array(items.into_iter().map(toml_to_json).collect()),
+        toml::Value::Table(table) => Value::Object(table.into_iter().map(|(k, v)| (k, toml_to_json(v))).collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parses_yaml_and_keeps_line_numbers() {
+        let (front_matter, rest) = extract("---\ntitle: Notes\ntags: [a, b]\n---\n# Body\n");
+        let front_matter = front_matter.unwrap();
+        assert_eq!(front_matter.format, FrontMatterFormat::Yaml);
+        assert_eq!(front_matter.raw, "title: Notes\ntags: [a, b]\n");
+        assert_eq!(front_matter.data, Some(json!({ "title": "Notes", "tags": ["a", "b"] })));
+        assert_eq!(rest, "\n\n\n\n# Body\n");
+    }
+
+    #[test]
+    fn parses_toml() {
+        let (front_matter, _) = extract("+++\ntitle = \"Notes\"\ndate = 2024-05-01\n[extra]\ndraft = true\n+++\nBody");
+        let front_matter = front_matter.unwrap();
+        assert_eq!(front_matter.format, FrontMatterFormat::Toml);
+        assert_eq!(front_matter.data, Some(json!({ "title": "Notes", "date": "2024-05-01", "extra": { "draft": true } })));
+    }
+
+    #[test]
+    fn accepts_a_bom_and_the_yaml_document_end_marker() {
+        let (front_matter, rest) = extract("\u{feff}---\na: 1\n...\nBody");
+        assert_eq!(front_matter.unwrap().data, Some(json!({ "a": 1 })));
+        assert_eq!(rest, "\n\n\nBody");
+
+        let (front_matter, _) = extract("---\n---\nBody");
+        assert_eq!(front_matter.unwrap().data, Some(json!({})));
+    }
+
+    #[test]
+    fn reports_invalid_blocks_without_dropping_them() {
+        let (front_matter, rest) = extract("---\na: [unclosed\n---\nBody");
+        let front_matter = front_matter.unwrap();
+        assert_eq!(front_matter.data, None);
+        assert!(front_matter.error.is_some());
+        assert_eq!(rest, "\n\n\nBody");
+    }
+
+    #[test]
+    fn only_accepts_mappings() {
+        for content in ["---\njust text\n---\nBody", "---\n- a\n- b\n---\nBody"] {
+            let front_matter = extract(content).0.unwrap();
+            assert_eq!(front_matter.data, None, "{:?}", content);
+            assert!(front_matter.error.is_some(), "{:?}", content);
+        }
+        assert_eq!(extract("---\n# just a comment\n---\nBody").0.unwrap().data, Some(json!({})));
+    }
+
+    #[test]
+    fn leaves_documents_without_front_matter_alone() {
+        for content in ["# Title\n", "---\nnever closed\n", "Text\n---\nmore\n---\n", " ---\na: 1\n---\n", ""] {
+            let (front_matter, rest) = extract(content);
+            assert!(front_matter.is_none(), "{:?}", content);
+            assert_eq!(rest, content);
+        }
+    }
+}
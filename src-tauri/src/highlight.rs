@@ -0,0 +1,74 @@
+use comrak::adapters::SyntaxHighlighterAdapter;
+use comrak::html::write_opening_tag;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use std::sync::OnceLock;
+use syntect::html::{ClassStyle, ClassedHTMLGenerator};
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+use crate::markdown::escape_html;
+
+/// Prefix of the scope classes on highlighted spans, e.g. `syn-keyword`.
+/// The colours live in the stylesheets, so they follow the light/dark theme.
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "syn-" };
+
+/// Marks a `<code>` element whose content was highlighted here, so the viewer
+/// doesn't highlight it again.
+const HIGHLIGHTED_CLASS: &str = "syn-highlighted";
+
+/// Loading the bundled grammars takes a while, so it's done once.
+fn syntax_set() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+/// Highlights fenced code blocks with syntect, emitting `<span>`s with scope
+/// classes instead of inline colours. Blocks in a language syntect doesn't
+/// know (including `mermaid`) are written out unchanged.
+pub struct ClassHighlighter;
+
+impl ClassHighlighter {
+    fn find_syntax(lang: Option<&str>) -> Option<&'static SyntaxReference> {
+        let lang = lang.filter(|l| !l.is_empty())?;
+        syntax_set().find_syntax_by_token(lang)
+    }
+
+    fn highlight(code: &str, syntax: &SyntaxReference) -> Option<String> {
+        let mut generator = ClassedHTMLGenerator::new_with_class_style(syntax, syntax_set(), CLASS_STYLE);
+        for line in LinesWithEndings::from(code) {
+            generator.parse_html_for_line_which_includes_newline(line).ok()?;
+        }
+        Some(generator.finalize())
+    }
+}
+
+impl SyntaxHighlighterAdapter for ClassHighlighter {
+    fn write_highlighted(&self, output: &mut dyn Write, lang: Option<&str>, code: &str) -> io::Result<()> {
+        let highlighted = Self::find_syntax(lang).and_then(|syntax| Self::highlight(code, syntax));
+        match highlighted {
+            Some(html) => output.write_all(html.as_bytes()),
+            None => output.write_all(escape_html(code).as_bytes()),
+        }
+    }
+
+    fn write_pre_tag(&self, output: &mut dyn Write, attributes: HashMap<String, String>) -> io::Result<()> {
+        write_opening_tag(output, "pre", sorted(attributes))
+    }
+
+    fn write_code_tag(&self, output: &mut dyn Write, mut attributes: HashMap<String, String>) -> io::Result<()> {
+        let lang = attributes.get("class").and_then(|c| c.strip_prefix("language-"));
+        if Self::find_syntax(lang).is_some() {
+            let class = attributes.entry("class".to_string()).or_default();
+            class.push(' ');
+            class.push_str(HIGHLIGHTED_CLASS);
+        }
+        write_opening_tag(output, "code", sorted(attributes))
+    }
+}
+
+/// comrak hands attributes over in a `HashMap`; sort them so the same
+/// document always renders to the same HTML.
+fn sorted(attributes: HashMap<String, String>) -> BTreeMap<String, String> {
+    attributes.into_iter().collect()
+}
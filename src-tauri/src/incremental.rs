@@ -0,0 +1,484 @@
+use comrak::adapters::{HeadingAdapter, HeadingMeta};
+use comrak::nodes::{Ast, AstNode, NodeValue, Sourcepos};
+use comrak::{format_html_with_plugins, parse_document, Anchorizer, Arena, ComrakOptions, ComrakPlugins};
+use regex::{Captures, Regex};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use crate::analysis::{self, plain_text, Heading};
+use crate::callouts;
+use crate::dialect::DialectProfile;
+use crate::embeds::Embeds;
+use crate::extensions;
+use crate::frontmatter::{self, FrontMatter};
+use crate::highlight::ClassHighlighter;
+use crate::links;
+use crate::markdown::{self, RenderOptions};
+use crate::math::Math;
+use crate::sanitize::sanitize_html;
+use crate::toc;
+
+static REFERENCE_DEFINITION: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?m)^ {0,3}\[[^\]^][^\]]*\]:.*$").unwrap());
+static HTML_TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<(/?)([A-Za-z][A-Za-z0-9-]*)(?:\s[^>]*?)?(/?)>").unwrap());
+static SOURCEPOS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"data-sourcepos="(\d+):(\d+)-(\d+):(\d+)""#).unwrap());
+
+/// Elements that never have a closing tag.
+const VOID_ELEMENTS: [&str; 14] = ["area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source", "track", "wbr"];
+
+/// One top-level block of the document. `html` is only sent when the viewer
+/// doesn't already have the block under this `key`; otherwise it reuses its
+/// element, moving its `data-sourcepos` lines if `start_line` changed.
+#[derive(Serialize)]
+pub struct RenderedBlock {
+    pub key: String,
+    pub start_line: usize,
+    pub end_line: usize,
+    pub html: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct RenderedBlocks {
+    pub blocks: Vec<RenderedBlock>,
+    pub front_matter: Option<FrontMatter>,
+}
+
+struct CachedBlock {
+    html: String,
+    /// Line the block started on when `html` was rendered.
+    start_line: usize,
+}
+
+/// Renders a document block by block, reusing the HTML of blocks whose
+/// source hasn't changed since the previous render. One cache per open
+/// document.
+///
+/// The whole document is still parsed every time, since a change can affect
+/// how the rest is split into blocks. A block's key covers its source text
+/// and everything else that goes into its HTML: its heading ids (which
+/// depend on earlier headings), footnote numbers, the headings listed by a
+/// `[TOC]`, the link reference definitions, where local links point and the
+/// render options. Blocks with embedded notes are always rendered again,
+/// since the notes live in other files.
+#[derive(Default)]
+pub struct BlockCache {
+    blocks: HashMap<String, CachedBlock>,
+    generation: u64,
+}
+
+impl BlockCache {
+    pub fn render(&mut self, content: &str, path: Option<&Path>, options: RenderOptions, known: &HashSet<String>) -> RenderedBlocks {
+        self.generation += 1;
+        let profile = options.profile();
+        let base_dir = path.and_then(|p| p.parent());
+        let (front_matter, content) = frontmatter::extract(content);
+        let lines: Vec<&str> = content.split('\n').collect();
+
+        let mut stack: Vec<PathBuf> = path.and_then(|p| std::fs::canonicalize(p).ok()).into_iter().collect();
+        let mut embeds = Embeds::new(base_dir, options, &mut stack);
+        let mut math = Math::new();
+        let processed = markdown::preprocess(&content, &profile, base_dir, &mut embeds, &mut math);
+        let processed_lines: Vec<&str> = processed.split('\n').collect();
+
+        let comrak_options = markdown::comrak_options(&profile, true);
+        let arena = Arena::new();
+        let root = parse_document(&arena, &processed, &comrak_options);
+        extensions::apply(&arena, root, &profile);
+        if profile.embeds {
+            embeds.place(&arena, root);
+        }
+        if let Some(base_dir) = base_dir.filter(|_| options.asset_urls) {
+            links::resolve(&arena, root, base_dir);
+        }
+
+        let toc_headings = if profile.toc && toc::contains_marker(root) {
+            analysis::heading_tree(root)
+        } else {
+            Vec::new()
+        };
+
+        let mut context = DefaultHasher::new();
+        (options.dialect.id(), options.sanitize, options.asset_urls, options.superscript, options.subscript).hash(&mut context);
+        reference_definitions(&content).hash(&mut context);
+        let context = context.finish();
+
+        let renderer = BlockRenderer {
+            arena: &arena,
+            options,
+            profile,
+            comrak_options: &comrak_options,
+            toc_headings: &toc_headings,
+        };
+        let mut anchorizer = Anchorizer::new();
+        let mut occurrences: HashMap<u64, usize> = HashMap::new();
+        let mut blocks = Vec::new();
+        let mut cache = HashMap::new();
+
+        for nodes in split_blocks(root) {
+            let (start_line, end_line) = line_range(&nodes);
+            let slugs: Vec<String> = nodes
+                .iter()
+                .flat_map(|node| node.descendants())
+                .filter(|node| matches!(node.data.borrow().value, NodeValue::Heading(_)))
+                .map(|node| anchorizer.anchorize(plain_text(node)))
+                .collect();
+
+            let mut hasher = DefaultHasher::new();
+            context.hash(&mut hasher);
+            for node in &nodes {
+                let pos = node.data.borrow().sourcepos;
+                (pos.start.line - start_line).hash(&mut hasher);
+                source_lines(&lines, pos.start.line, pos.end.line).hash(&mut hasher);
+            }
+            slugs.hash(&mut hasher);
+            footnote_numbers(&nodes).hash(&mut hasher);
+            link_targets(&nodes).hash(&mut hasher);
+            if !toc_headings.is_empty() && nodes.iter().any(|node| toc::contains_marker(node)) {
+                toc_fingerprint(&toc_headings).hash(&mut hasher);
+            }
+            let hash = hasher.finish();
+
+            // Identical blocks (two `---` rules, say) still need their own keys
+            let occurrence = occurrences.entry(hash).or_default();
+            *occurrence += 1;
+            let mut key = format!("{:016x}", hash);
+            if *occurrence > 1 {
+                key.push_str(&format!("-{}", occurrence));
+            }
+            if source_lines(&processed_lines, start_line, end_line).iter().any(|l| l.contains("data-markpad-embed")) {
+                key.push_str(&format!("-g{}", self.generation));
+            }
+
+            let html = if known.contains(&key) {
+                None
+            } else if let Some(cached) = self.blocks.get(&key) {
+                Some(shift_sourcepos(&cached.html, start_line as isize - cached.start_line as isize))
+            } else {
+                Some(renderer.render(&nodes, slugs, &embeds, &math))
+            };
+
+            match &html {
+                Some(html) => {
+                    cache.insert(key.clone(), CachedBlock { html: html.clone(), start_line });
+                }
+                None => {
+                    if let Some(cached) = self.blocks.remove(&key) {
+                        cache.insert(key.clone(), cached);
+                    }
+                }
+            }
+            blocks.push(RenderedBlock { key, start_line, end_line, html });
+        }
+
+        self.blocks = cache;
+        RenderedBlocks { blocks, front_matter }
+    }
+}
+
+struct BlockRenderer<'a, 'o> {
+    arena: &'a Arena<AstNode<'a>>,
+    options: RenderOptions,
+    profile: DialectProfile,
+    comrak_options: &'o ComrakOptions,
+    toc_headings: &'o [Heading],
+}
+
+impl<'a, 'o> BlockRenderer<'a, 'o> {
+    /// Formats `nodes` on their own, moving them into a document of their
+    /// own. Runs the same passes as a full render, with the heading ids
+    /// worked out from the whole document.
+    fn render(&self, nodes: &[&'a AstNode<'a>], slugs: Vec<String>, embeds: &Embeds, math: &Math) -> String {
+        let document = self.arena.alloc(AstNode::new(RefCell::new(Ast::new(NodeValue::Document, (1, 1).into()))));
+        for node in nodes {
+            document.append(node);
+        }
+        if self.profile.callouts {
+            callouts::expand(self.arena, document);
+        }
+        if self.profile.toc {
+            toc::expand_with(document, self.toc_headings);
+        }
+
+        let heading_ids = HeadingIds { ids: RefCell::new(slugs.into()) };
+        let mut plugins = ComrakPlugins::default();
+        plugins.render.codefence_syntax_highlighter = Some(&ClassHighlighter);
+        if self.profile.header_ids {
+            plugins.render.heading_adapter = Some(&heading_ids);
+        }
+
+        let mut html = Vec::new();
+        format_html_with_plugins(document, self.comrak_options, &mut html, &plugins).unwrap();
+        let html = embeds.splice(&math.splice(&String::from_utf8_lossy(&html)));
+        if self.options.sanitize {
+            sanitize_html(&html)
+        } else {
+            html
+        }
+    }
+}
+
+/// Writes headings as comrak's `header_ids` extension does, but with ids
+/// handed in from outside, since a block rendered on its own can't see the
+/// headings before it.
+struct HeadingIds {
+    ids: RefCell<VecDeque<String>>,
+}
+
+impl HeadingAdapter for HeadingIds {
+    fn enter(&self, output: &mut dyn Write, heading: &HeadingMeta, sourcepos: Option<Sourcepos>) -> io::Result<()> {
+        write!(output, "<h{}", heading.level)?;
+        if let Some(sourcepos) = sourcepos {
+            write!(output, " data-sourcepos=\"{}\"", sourcepos)?;
+        }
+        let id = self.ids.borrow_mut().pop_front().unwrap_or_default();
+        write!(output, "><a href=\"#{}\" aria-hidden=\"true\" class=\"anchor\" id=\"{}\"></a>", id, id)
+    }
+
+    fn exit(&self, output: &mut dyn Write, heading: &HeadingMeta) -> io::Result<()> {
+        writeln!(output, "</h{}>", heading.level)
+    }
+}
+
+/// The document's top-level nodes, one block each, with two exceptions.
+/// Footnote definitions: comrak moves them all to the end and wraps them in
+/// a single `<section>`, so they form one block together. And raw HTML that
+/// opens an element in one block and closes it in a later one, like a
+/// `<div align="center">` or `<details>` around Markdown: the viewer parses
+/// each block's HTML on its own, so everything up to the closing tag is one
+/// block.
+fn split_blocks<'a>(root: &'a AstNode<'a>) -> Vec<Vec<&'a AstNode<'a>>> {
+    let mut blocks: Vec<Vec<&'a AstNode<'a>>> = Vec::new();
+    let mut footnotes = Vec::new();
+    let mut open: HashMap<String, usize> = HashMap::new();
+    for node in root.children() {
+        if let NodeValue::FootnoteDefinition(_) = node.data.borrow().value {
+            footnotes.push(node);
+            continue;
+        }
+        match blocks.last_mut() {
+            Some(block) if !open.is_empty() => block.push(node),
+            _ => blocks.push(vec![node]),
+        }
+        count_html_tags(node, &mut open);
+    }
+    if !footnotes.is_empty() {
+        blocks.push(footnotes);
+    }
+    blocks
+}
+
+/// Adds the elements `node`'s raw HTML opens to `open` and takes away the
+/// ones it closes. Closing tags with nothing open are ignored.
+fn count_html_tags<'a>(node: &'a AstNode<'a>, open: &mut HashMap<String, usize>) {
+    for descendant in node.descendants() {
+        let ast = descendant.data.borrow();
+        let html = match &ast.value {
+            NodeValue::HtmlBlock(block) => &block.literal,
+            NodeValue::HtmlInline(html) => html,
+            _ => continue,
+        };
+        for caps in HTML_TAG.captures_iter(html) {
+            let name = caps[2].to_ascii_lowercase();
+            if VOID_ELEMENTS.contains(&name.as_str()) || !caps[3].is_empty() {
+                continue;
+            }
+            if caps[1].is_empty() {
+                *open.entry(name).or_default() += 1;
+            } else if let Some(count) = open.get_mut(&name) {
+                *count -= 1;
+                if *count == 0 {
+                    open.remove(&name);
+                }
+            }
+        }
+    }
+}
+
+fn line_range<'a>(nodes: &[&'a AstNode<'a>]) -> (usize, usize) {
+    let start = nodes.iter().map(|n| n.data.borrow().sourcepos.start.line).min().unwrap_or(0);
+    let end = nodes.iter().map(|n| n.data.borrow().sourcepos.end.line).max().unwrap_or(0);
+    (start, end.max(start))
+}
+
+/// Lines `start..=end` (1-based) of the document.
+fn source_lines<'s>(lines: &'s [&'s str], start: usize, end: usize) -> &'s [&'s str] {
+    let end = end.min(lines.len());
+    let start = start.max(1).min(end + 1);
+    &lines[start - 1..end]
+}
+
+fn footnote_numbers<'a>(nodes: &[&'a AstNode<'a>]) -> Vec<String> {
+    nodes
+        .iter()
+        .flat_map(|node| node.descendants())
+        .filter_map(|node| match &node.data.borrow().value {
+            NodeValue::FootnoteReference(number) => Some(number.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Where the links and images point after `links::resolve`, which depends
+/// on the document's folder and on which files exist, not just the source.
+fn link_targets<'a>(nodes: &[&'a AstNode<'a>]) -> Vec<String> {
+    nodes
+        .iter()
+        .flat_map(|node| node.descendants())
+        .filter_map(|node| match &node.data.borrow().value {
+            NodeValue::Link(link) | NodeValue::Image(link) => Some(link.url.clone()),
+            NodeValue::HtmlInline(html) => Some(html.clone()),
+            NodeValue::HtmlBlock(block) => Some(block.literal.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn toc_fingerprint(headings: &[Heading]) -> Vec<(u8, &str, &str)> {
+    let mut out = Vec::new();
+    for heading in headings {
+        out.push((heading.level, heading.text.as_str(), heading.slug.as_str()));
+        out.extend(toc_fingerprint(&heading.children));
+    }
+    out
+}
+
+/// `[label]: url` lines, which change what links elsewhere resolve to.
+/// Footnote definitions (`[^1]: ...`) are blocks of their own.
+fn reference_definitions(content: &str) -> Vec<&str> {
+    REFERENCE_DEFINITION.find_iter(content).map(|m| m.as_str()).collect()
+}
+
+/// Moves every `data-sourcepos` in `html` down by `delta` lines.
+fn shift_sourcepos(html: &str, delta: isize) -> String {
+    if delta == 0 {
+        return html.to_string();
+    }
+    let shift = |line: &str| line.parse::<isize>().unwrap_or(0) + delta;
+    SOURCEPOS.replace_all(html, |caps: &Captures| {
+        format!("data-sourcepos=\"{}:{}-{}:{}\"", shift(&caps[1]), &caps[2], shift(&caps[3]), &caps[4])
+    })
+    .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::Dialect;
+
+    const DOCUMENT: &str = "# Intro\n\n[TOC]\n\nText with a note[^1] and [a link][ref].\n\n## Intro\n\n> [!TIP]\n> Callout.\n\n## Intro\n\n$$x^2$$\n\n[ref]: https://example.com\n[^1]: The note.\n";
+
+    fn options() -> RenderOptions {
+        RenderOptions { dialect: Dialect::Gfm, ..RenderOptions::default() }
+    }
+
+    fn joined(blocks: &RenderedBlocks) -> String {
+        blocks.blocks.iter().map(|b| b.html.clone().unwrap()).collect()
+    }
+
+    fn render_fresh(content: &str) -> RenderedBlocks {
+        BlockCache::default().render(content, None, options(), &HashSet::new())
+    }
+
+    #[test]
+    fn blocks_add_up_to_the_full_render() {
+        let full = markdown::render_markdown(DOCUMENT, None, options()).html;
+        assert_eq!(joined(&render_fresh(DOCUMENT)), full);
+        // Repeated headings get the same numbered ids as in the full render
+        assert!(full.contains("id=\"intro-1\"") && full.contains("id=\"intro-2\""));
+    }
+
+    #[test]
+    fn only_changed_blocks_are_sent_again() {
+        let mut cache = BlockCache::default();
+        let first = cache.render("One\n\nTwo\n\nThree\n", None, options(), &HashSet::new());
+        let known: HashSet<String> = first.blocks.iter().map(|b| b.key.clone()).collect();
+
+        let second = cache.render("One\n\nTwo, edited\n\nThree\n", None, options(), &known);
+        let sent: Vec<bool> = second.blocks.iter().map(|b| b.html.is_some()).collect();
+        assert_eq!(sent, [false, true, false]);
+        assert_eq!(second.blocks[0].key, first.blocks[0].key);
+        assert_eq!(second.blocks[2].key, first.blocks[2].key);
+    }
+
+    #[test]
+    fn moved_blocks_keep_their_key_and_get_new_lines() {
+        let mut cache = BlockCache::default();
+        let first = cache.render("One\n\nTwo\n", None, options(), &HashSet::new());
+        let second = cache.render("New\n\nOne\n\nTwo\n", None, options(), &HashSet::new());
+
+        assert_eq!(second.blocks[1].key, first.blocks[0].key);
+        assert_eq!((second.blocks[1].start_line, second.blocks[1].end_line), (3, 3));
+        // Cached HTML is moved to the block's new lines
+        assert_eq!(joined(&second), markdown::render_markdown("New\n\nOne\n\nTwo\n", None, options()).html);
+    }
+
+    #[test]
+    fn heading_changes_reach_the_blocks_that_depend_on_them() {
+        let mut cache = BlockCache::default();
+        let first = cache.render(DOCUMENT, None, options(), &HashSet::new());
+        let known: HashSet<String> = first.blocks.iter().map(|b| b.key.clone()).collect();
+
+        // Renaming the first heading changes the ids of the later ones and
+        // the [TOC] listing them. The last heading now looks just like the
+        // second one did, so the viewer can reuse that element for it.
+        let edited = DOCUMENT.replacen("# Intro", "# Start", 1);
+        let second = cache.render(&edited, None, options(), &known);
+        let sent: Vec<usize> = second.blocks.iter().filter(|b| b.html.is_some()).map(|b| b.start_line).collect();
+        assert_eq!(sent, [1, 3, 7]);
+        assert_eq!(second.blocks.iter().find(|b| b.start_line == 12).unwrap().key, first.blocks.iter().find(|b| b.start_line == 7).unwrap().key);
+        assert_eq!(joined(&render_fresh(&edited)), markdown::render_markdown(&edited, None, options()).html);
+
+        let edited = DOCUMENT.replacen("## Intro\n\n>", "## Other\n\n>", 1);
+        let third = BlockCache::default().render(&edited, None, options(), &HashSet::new());
+        assert_eq!(joined(&third), markdown::render_markdown(&edited, None, options()).html);
+    }
+
+    /// Elements opened but not closed, or closed but not opened, in `html`.
+    fn unbalanced(html: &str) -> Vec<String> {
+        let mut open: Vec<String> = Vec::new();
+        let mut stray = Vec::new();
+        for caps in HTML_TAG.captures_iter(html) {
+            let name = caps[2].to_ascii_lowercase();
+            if VOID_ELEMENTS.contains(&name.as_str()) || !caps[3].is_empty() {
+                continue;
+            }
+            if caps[1].is_empty() {
+                open.push(name);
+            } else if open.last() == Some(&name) {
+                open.pop();
+            } else {
+                stray.push(format!("/{}", name));
+            }
+        }
+        open.into_iter().chain(stray).collect()
+    }
+
+    #[test]
+    fn raw_html_around_markdown_stays_in_one_block() {
+        let content = "<div align=\"center\">\n\n![logo](x.png)\n\n# Title\n\n</div>\n\n<details>\n<summary>More</summary>\n\n- a\n- b\n\n</details>\n\nAfter\n";
+        let blocks = render_fresh(content);
+        let lines: Vec<(usize, usize)> = blocks.blocks.iter().map(|b| (b.start_line, b.end_line)).collect();
+        assert_eq!(lines, [(1, 7), (9, 15), (17, 17)]);
+        for block in &blocks.blocks {
+            let html = block.html.as_deref().unwrap();
+            assert!(unbalanced(html).is_empty(), "{:?} in {}", unbalanced(html), html);
+        }
+        assert_eq!(joined(&blocks), markdown::render_markdown(content, None, options()).html);
+
+        // An element that is never closed takes the rest of the document
+        let blocks = render_fresh("<div>\n\nOne\n\nTwo\n");
+        assert_eq!(blocks.blocks.len(), 1);
+    }
+
+    #[test]
+    fn identical_blocks_get_their_own_keys() {
+        let blocks = render_fresh("***\n\n***\n\nSame\n\nSame\n");
+        let keys: HashSet<&str> = blocks.blocks.iter().map(|b| b.key.as_str()).collect();
+        assert_eq!(keys.len(), 4);
+    }
+}
@@ -1,20 +1,54 @@
-use comrak::{markdown_to_html, ComrakExtensionOptions, ComrakOptions};
 use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::Path;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 use tauri::{AppHandle, Emitter, Manager, State};
 use tauri::menu::ContextMenu;
-use regex::{Regex, Captures};
-use std::borrow::Cow;
 
 
 struct WatcherState {
     watcher: Mutex<Option<RecommendedWatcher>>,
 }
 
+struct SettingsState {
+    settings: Mutex<Settings>,
+}
+
+/// Block caches for incremental previews, by the viewer's tab id.
+struct BlockCacheState {
+    caches: Mutex<HashMap<String, Arc<Mutex<BlockCache>>>>,
+}
+
 
+mod analysis;
+mod callouts;
+mod cli;
+pub mod dialect;
+mod embeds;
+mod export;
+mod extensions;
+mod files;
+mod frontmatter;
+mod highlight;
+pub mod incremental;
+mod linkcheck;
+mod links;
+pub mod markdown;
+mod math;
+mod render_queue;
+mod sanitize;
+mod settings;
 mod setup;
+#[cfg(test)]
+mod test_support;
+mod toc;
+mod wikilinks;
+
+use dialect::Dialect;
+use incremental::BlockCache;
+use render_queue::RenderQueue;
+use settings::Settings;
 
 
 
@@ -23,89 +57,254 @@ async fn show_window(window: tauri::Window) {
     window.show().unwrap();
 }
 
-fn encode_image_paths(content: &str) -> Cow<'_, str> {
-    let re = Regex::new(r"!\[([^\]]*)\]\(([^)]+)\)").unwrap();
-    re.replace_all(content, |caps: &Captures| {
-        let alt = &caps[1];
-        let url = &caps[2];
-        if url.starts_with("http://") || url.starts_with("https://") || url.starts_with("data:") || !url.contains(' ') {
-            return caps[0].to_string();
-        }
-        let encoded = url.replace(' ', "%20");
-        format!("![{}]({})", alt, encoded)
+fn render_options(state: &State<'_, SettingsState>, path: Option<&str>) -> markdown::RenderOptions {
+    state.settings.lock().unwrap().render_options(path.map(Path::new))
+}
+
+/// Options for what the viewer shows, which loads local images and links
+/// through the asset protocol.
+fn preview_options(state: &State<'_, SettingsState>, path: Option<&str>) -> markdown::RenderOptions {
+    markdown::RenderOptions { asset_urls: true, ..render_options(state, path) }
+}
+
+/// Applies `change` to the saved settings and tells the window to re-render.
+fn update_settings(app: &AppHandle, change: impl FnOnce(&mut Settings)) -> Result<(), String> {
+    let state = app.state::<SettingsState>();
+    let mut settings = state.settings.lock().unwrap();
+    change(&mut settings);
+    settings.save()?;
+    if let Some(window) = app.get_webview_window("main") {
+        let _ = window.emit("render-settings-changed", ());
+    }
+    Ok(())
+}
+
+// The render commands run on a worker thread and take the viewer's tab id
+// and request number; they return `None` when a newer request for the same
+// tab made the result stale (see `RenderQueue`).
+
+#[tauri::command]
+async fn open_markdown(
+    app: AppHandle,
+    state: State<'_, SettingsState>,
+    doc_id: String,
+    generation: u64,
+    path: String,
+) -> Result<Option<markdown::RenderedMarkdown>, String> {
+    let options = preview_options(&state, Some(&path));
+    render_queue::run(&app, doc_id, generation, move || {
+        let content = files::read_to_string(Path::new(&path)).map_err(|e| e.to_string())?;
+        Ok(markdown::render_markdown(&content, Some(Path::new(&path)), options))
     })
+    .await?
+    .transpose()
 }
 
-fn process_obsidian_embeds(content: &str) -> Cow<'_, str> {
-    let re = Regex::new(r"!\[\[(.*?)\]\]").unwrap();
-    
-    re.replace_all(content, |caps: &Captures| {
-        let inner = &caps[1];
-        let mut parts = inner.split('|');
-        let path = parts.next().unwrap_or("");
-        let size = parts.next();
-        
-        let path_escaped = path.replace("\"", "&quot;");
-        
-        if let Some(size_str) = size {
-            if size_str.contains('x') {
-                let mut dims = size_str.split('x');
-                let width = dims.next().unwrap_or("");
-                let height = dims.next().unwrap_or("");
-                format!("<img src=\"{}\" width=\"{}\" height=\"{}\" alt=\"{}\" />", path_escaped, width, height, path)
-            } else {
-                format!("<img src=\"{}\" width=\"{}\" alt=\"{}\" />", path_escaped, size_str, path)
-            }
-        } else {
-             format!("<img src=\"{}\" alt=\"{}\" />", path_escaped, path)
+#[tauri::command]
+async fn render_markdown(
+    app: AppHandle,
+    state: State<'_, SettingsState>,
+    doc_id: String,
+    generation: u64,
+    content: String,
+    path: Option<String>,
+) -> Result<Option<markdown::RenderedMarkdown>, String> {
+    let options = preview_options(&state, path.as_deref());
+    render_queue::run(&app, doc_id, generation, move || {
+        markdown::render_markdown(&content, path.as_deref().map(Path::new), options)
+    })
+    .await
+}
+
+/// Renders the split-view preview of tab `doc_id` block by block, sending
+/// HTML only for blocks that aren't in `known`.
+#[tauri::command]
+async fn render_markdown_blocks(
+    app: AppHandle,
+    state: State<'_, SettingsState>,
+    doc_id: String,
+    generation: u64,
+    content: String,
+    path: Option<String>,
+    known: Vec<String>,
+) -> Result<Option<incremental::RenderedBlocks>, String> {
+    let options = preview_options(&state, path.as_deref());
+    let cache = app.state::<BlockCacheState>().caches.lock().unwrap().entry(doc_id.clone()).or_default().clone();
+    render_queue::run(&app, doc_id, generation, move || {
+        let known: HashSet<String> = known.into_iter().collect();
+        cache.lock().unwrap().render(&content, path.as_deref().map(Path::new), options, &known)
+    })
+    .await
+}
+
+#[tauri::command]
+fn release_block_cache(blocks: State<'_, BlockCacheState>, queue: State<'_, RenderQueue>, doc_id: String) {
+    blocks.caches.lock().unwrap().remove(&doc_id);
+    queue.forget(&side_queue_id(&doc_id, "outline"));
+    queue.forget(&side_queue_id(&doc_id, "info"));
+    queue.forget(&doc_id);
+}
+
+/// Word count, reading time, links and images of the editor's text, for the
+/// status bar. Parsed without rendering, and queued apart from the tab's
+/// renders like `document_outline`.
+#[tauri::command]
+async fn document_info(
+    app: AppHandle,
+    state: State<'_, SettingsState>,
+    doc_id: String,
+    generation: u64,
+    content: String,
+    path: Option<String>,
+) -> Result<Option<analysis::DocumentInfo>, String> {
+    let options = render_options(&state, path.as_deref());
+    render_queue::run(&app, side_queue_id(&doc_id, "info"), generation, move || {
+        analysis::document_info(&content, path.as_deref().map(Path::new), options)
+    })
+    .await
+}
+
+/// Headings of `content`, or of the file at `path` when there is none. Queued
+/// apart from the tab's renders, so an outline request doesn't overtake them.
+#[tauri::command]
+async fn document_outline(
+    app: AppHandle,
+    state: State<'_, SettingsState>,
+    doc_id: String,
+    generation: u64,
+    path: Option<String>,
+    content: Option<String>,
+) -> Result<Option<Vec<analysis::Heading>>, String> {
+    let dialect = render_options(&state, path.as_deref()).dialect;
+    render_queue::run(&app, side_queue_id(&doc_id, "outline"), generation, move || {
+        let content = match (content, path) {
+            (Some(content), _) => content,
+            (None, Some(path)) => files::read_to_string(Path::new(&path)).map_err(|e| e.to_string())?,
+            (None, None) => String::new(),
+        };
+        Ok(analysis::outline(&content, dialect))
+    })
+    .await?
+    .transpose()
+}
+
+/// Queue key for a tab's requests that shouldn't cancel its renders.
+fn side_queue_id(doc_id: &str, kind: &str) -> String {
+    format!("{}:{}", doc_id, kind)
+}
+
+/// Broken links, missing images and unresolved embeds in the document at
+/// `path` (checking `content` when the editor has unsaved changes), or in
+/// every Markdown file when `path` is a folder. Without a path only
+/// `#anchors` within `content` are checked.
+#[tauri::command]
+async fn check_links(
+    state: State<'_, SettingsState>,
+    path: Option<String>,
+    content: Option<String>,
+) -> Result<Vec<linkcheck::BrokenLink>, String> {
+    let settings = state.settings.lock().unwrap().clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let path = path.as_deref().map(Path::new);
+        let dialect_for = |p: &Path| settings.dialect_for(Some(p));
+        if let Some(dir) = path.filter(|p| p.is_dir()) {
+            return linkcheck::check_folder(dir, &dialect_for);
         }
+        let content = match (content, path) {
+            (Some(content), _) => content,
+            (None, Some(path)) => files::read_to_string(path).map_err(|e| e.to_string())?,
+            (None, None) => String::new(),
+        };
+        Ok(linkcheck::check_document(&content, path, settings.dialect_for(path), &dialect_for))
     })
+    .await
+    .map_err(|e| e.to_string())?
 }
 
 #[tauri::command]
-fn convert_markdown(content: &str) -> String {
-    let with_encoded_paths = encode_image_paths(content);
-    let processed = process_obsidian_embeds(&with_encoded_paths);
-    
-    let mut options = ComrakOptions {
-        extension: ComrakExtensionOptions {
-            strikethrough: true,
-            table: true,
-            autolink: true,
-            tasklist: true,
-            superscript: false,
-            footnotes: true,
-            description_lists: true,
-            ..ComrakExtensionOptions::default()
-        },
-        ..ComrakOptions::default()
+fn export_html(state: State<'_, SettingsState>, path: String, content: Option<String>, output: String) -> Result<(), String> {
+    let options = render_options(&state, Some(&path));
+    let content = match content {
+        Some(content) => content,
+        None => files::read_to_string(Path::new(&path)).map_err(|e| e.to_string())?,
     };
-    options.render.unsafe_ = true;
-    options.render.hardbreaks = true;
-    options.render.sourcepos = true;
+    let html = export::export_document(&content, Path::new(&path), options);
+    files::save(Path::new(&output), html.as_bytes(), files::Backups::Off)
+}
 
-    markdown_to_html(&processed, &options)
+#[derive(serde::Serialize)]
+struct DialectSettings {
+    default_dialect: Dialect,
+    file_dialect: Option<Dialect>,
+    profile: dialect::DialectProfile,
 }
 
 #[tauri::command]
-fn open_markdown(path: String) -> Result<String, String> {
-    let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
-    Ok(convert_markdown(&content))
+fn get_dialect_settings(state: State<'_, SettingsState>, path: Option<String>) -> DialectSettings {
+    let settings = state.settings.lock().unwrap();
+    let file_dialect = path.as_deref().and_then(|p| settings.file_dialect(Path::new(p)));
+    DialectSettings {
+        default_dialect: settings.dialect,
+        file_dialect,
+        profile: settings.render_options(path.as_deref().map(Path::new)).profile(),
+    }
+}
+
+#[tauri::command]
+fn set_default_dialect(app: AppHandle, dialect: Dialect) -> Result<(), String> {
+    update_settings(&app, |settings| settings.dialect = dialect)
+}
+
+#[tauri::command]
+fn set_file_dialect(app: AppHandle, path: String, dialect: Option<Dialect>) -> Result<(), String> {
+    update_settings(&app, |settings| settings.set_file_dialect(Path::new(&path), dialect))
 }
 
 #[tauri::command]
-fn render_markdown(content: String) -> String {
-    convert_markdown(&content)
+fn is_path_trusted(state: State<'_, SettingsState>, path: String) -> bool {
+    state.settings.lock().unwrap().is_trusted(Path::new(&path))
 }
 
+/// Trusts or stops trusting the folder containing `path`.
 #[tauri::command]
-fn read_file_content(path: String) -> Result<String, String> {
-    fs::read_to_string(path).map_err(|e| e.to_string())
+fn set_folder_trust(app: AppHandle, path: String, trusted: bool) -> Result<(), String> {
+    let folder = Path::new(&path).parent().ok_or("File has no parent folder")?.to_path_buf();
+    update_settings(&app, |settings| settings.set_folder_trust(&folder, trusted))
 }
 
+/// Reads a text file in whatever encoding it is in, or in `encoding` when
+/// the user picks one to reopen it with.
 #[tauri::command]
-fn save_file_content(path: String, content: String) -> Result<(), String> {
-    fs::write(path, content).map_err(|e| e.to_string())
+fn read_file_content(path: String, encoding: Option<String>) -> Result<files::TextFile, String> {
+    let encoding = encoding.as_deref().map(files::encoding_for_label).transpose()?;
+    files::read(Path::new(&path), encoding).map_err(|e| e.to_string())
+}
+
+/// Saves in `encoding` (UTF-8 when not given), with a byte order mark if
+/// `bom` is set, and every line break made `line_ending` (the platform's
+/// own when not given). With `expected`, the stamp the file had when it was
+/// read, refuses to overwrite changes made on disk since. Returns the
+/// file's new stamp.
+#[tauri::command]
+fn save_file_content(
+    state: State<'_, SettingsState>,
+    path: String,
+    content: String,
+    encoding: Option<String>,
+    bom: Option<bool>,
+    line_ending: Option<files::LineEnding>,
+    expected: Option<files::FileStamp>,
+) -> Result<files::FileStamp, files::SaveError> {
+    let path = Path::new(&path);
+    let backups = state.settings.lock().unwrap().backups;
+    let encoding = encoding.as_deref().map(files::encoding_for_label).transpose()?.unwrap_or(encoding_rs::UTF_8);
+    let content = line_ending.unwrap_or_else(files::LineEnding::native).apply(&content);
+    let bytes = files::encode(&content, encoding, bom.unwrap_or(false))?;
+    if let Some(expected) = &expected {
+        files::check_unchanged(path, expected)?;
+    }
+    files::save(path, &bytes, backups)?;
+    Ok(files::stamp(path).map_err(|e| e.to_string())?.unwrap_or_default())
 }
 
 #[tauri::command]
@@ -118,19 +317,25 @@ fn rename_file(old_path: String, new_path: String) -> Result<(), String> {
     fs::rename(old_path, new_path).map_err(|e| e.to_string())
 }
 
+/// Watches the file's folder rather than the file itself: saves that
+/// rename a new file over the old one, ours included, would otherwise end
+/// the watch.
 #[tauri::command]
 fn watch_file(handle: AppHandle, state: State<'_, WatcherState>, path: String) -> Result<(), String> {
     let mut watcher_lock = state.watcher.lock().unwrap();
 
     *watcher_lock = None;
 
-    let path_to_watch = path.clone();
+    let path_to_watch = fs::canonicalize(&path).unwrap_or_else(|_| Path::new(&path).to_path_buf());
+    let folder = path_to_watch.parent().ok_or("File has no parent folder")?.to_path_buf();
     let app_handle = handle.clone();
 
     let mut watcher = RecommendedWatcher::new(
         move |res: Result<notify::Event, notify::Error>| {
-            if let Ok(_) = res {
-                let _ = app_handle.emit("file-changed", ());
+            if let Ok(event) = res {
+                if event.paths.iter().any(|p| p == &path_to_watch) {
+                    let _ = app_handle.emit("file-changed", ());
+                }
             }
         },
         Config::default(),
@@ -138,7 +343,7 @@ fn watch_file(handle: AppHandle, state: State<'_, WatcherState>, path: String) -
     .map_err(|e| e.to_string())?;
 
     watcher
-        .watch(Path::new(&path_to_watch), RecursiveMode::NonRecursive)
+        .watch(&folder, RecursiveMode::NonRecursive)
         .map_err(|e| e.to_string())?;
 
     *watcher_lock = Some(watcher);
@@ -220,6 +425,7 @@ fn is_win11() -> bool {
 fn show_context_menu(
     app: AppHandle,
     state: State<'_, ContextMenuState>,
+    settings_state: State<'_, SettingsState>,
     window: tauri::Window,
     menu_type: String, // 'document', 'tab', 'tab_bar'
     path: Option<String>,
@@ -284,6 +490,21 @@ fn show_context_menu(
 
                 let edit = tauri::menu::MenuItem::with_id(&app, "ctx_edit", "Edit", true, None::<&str>).map_err(|e| e.to_string())?;
                 menu.append(&edit).map_err(|e| e.to_string())?;
+
+                let export = tauri::menu::MenuItem::with_id(&app, "ctx_export_html", "Export as HTML", true, None::<&str>).map_err(|e| e.to_string())?;
+                menu.append(&export).map_err(|e| e.to_string())?;
+
+                let settings = settings_state.settings.lock().unwrap();
+                let dialects = dialect_menu(&app, &settings, path.as_deref())?;
+                menu.append(&dialects).map_err(|e| e.to_string())?;
+
+                let trusted = path.as_deref().is_some_and(|p| settings.is_trusted(Path::new(p)));
+                drop(settings);
+                let trust = tauri::menu::CheckMenuItem::with_id(&app, "ctx_trust_folder", "Allow Raw HTML in This Folder", true, trusted, None::<&str>).map_err(|e| e.to_string())?;
+                menu.append(&trust).map_err(|e| e.to_string())?;
+
+                let backups = backups_menu(&app, &settings_state.settings.lock().unwrap())?;
+                menu.append(&backups).map_err(|e| e.to_string())?;
                 
                 // Add separator before close
                 let sep2 = tauri::menu::PredefinedMenuItem::separator(&app).map_err(|e| e.to_string())?;
@@ -299,6 +520,63 @@ fn show_context_menu(
     Ok(())
 }
 
+/// "Markdown Dialect" submenu: a choice for the file under the cursor plus
+/// the default for every other file.
+fn dialect_menu(app: &AppHandle, settings: &Settings, path: Option<&str>) -> Result<tauri::menu::Submenu<tauri::Wry>, String> {
+    let file_dialect = path.and_then(|p| settings.file_dialect(Path::new(p)));
+    let submenu = tauri::menu::Submenu::new(app, "Markdown Dialect", true).map_err(|e| e.to_string())?;
+
+    let use_default_label = format!("Use Default ({})", settings.dialect.label());
+    let use_default = tauri::menu::CheckMenuItem::with_id(app, "ctx_dialect_file_default", use_default_label, true, file_dialect.is_none(), None::<&str>).map_err(|e| e.to_string())?;
+    submenu.append(&use_default).map_err(|e| e.to_string())?;
+
+    for dialect in dialect::ALL_DIALECTS {
+        let id = format!("ctx_dialect_file_{}", dialect.id());
+        let item = tauri::menu::CheckMenuItem::with_id(app, id, dialect.label(), true, file_dialect == Some(dialect), None::<&str>).map_err(|e| e.to_string())?;
+        submenu.append(&item).map_err(|e| e.to_string())?;
+    }
+
+    let sep = tauri::menu::PredefinedMenuItem::separator(app).map_err(|e| e.to_string())?;
+    submenu.append(&sep).map_err(|e| e.to_string())?;
+
+    let defaults = tauri::menu::Submenu::new(app, "Default Dialect", true).map_err(|e| e.to_string())?;
+    for dialect in dialect::ALL_DIALECTS {
+        let id = format!("ctx_dialect_default_{}", dialect.id());
+        let item = tauri::menu::CheckMenuItem::with_id(app, id, dialect.label(), true, settings.dialect == dialect, None::<&str>).map_err(|e| e.to_string())?;
+        defaults.append(&item).map_err(|e| e.to_string())?;
+    }
+    submenu.append(&defaults).map_err(|e| e.to_string())?;
+
+    let sep = tauri::menu::PredefinedMenuItem::separator(app).map_err(|e| e.to_string())?;
+    submenu.append(&sep).map_err(|e| e.to_string())?;
+
+    let superscript = tauri::menu::CheckMenuItem::with_id(app, "ctx_superscript", "Superscript ^x^", true, settings.superscript, None::<&str>).map_err(|e| e.to_string())?;
+    submenu.append(&superscript).map_err(|e| e.to_string())?;
+    let subscript = tauri::menu::CheckMenuItem::with_id(app, "ctx_subscript", "Subscript ~x~", true, settings.subscript, None::<&str>).map_err(|e| e.to_string())?;
+    submenu.append(&subscript).map_err(|e| e.to_string())?;
+
+    Ok(submenu)
+}
+
+/// "Backups on Save" submenu: how old contents are kept, and a way to find
+/// the timestamped copies.
+fn backups_menu(app: &AppHandle, settings: &Settings) -> Result<tauri::menu::Submenu<tauri::Wry>, String> {
+    let submenu = tauri::menu::Submenu::new(app, "Backups on Save", true).map_err(|e| e.to_string())?;
+    for backups in files::ALL_BACKUPS {
+        let id = format!("ctx_backups_{}", backups.id());
+        let item = tauri::menu::CheckMenuItem::with_id(app, id, backups.label(), true, settings.backups == backups, None::<&str>).map_err(|e| e.to_string())?;
+        submenu.append(&item).map_err(|e| e.to_string())?;
+    }
+
+    let sep = tauri::menu::PredefinedMenuItem::separator(app).map_err(|e| e.to_string())?;
+    submenu.append(&sep).map_err(|e| e.to_string())?;
+
+    let show = tauri::menu::MenuItem::with_id(app, "ctx_show_backups", "Show Backups Folder", true, None::<&str>).map_err(|e| e.to_string())?;
+    submenu.append(&show).map_err(|e| e.to_string())?;
+
+    Ok(submenu)
+}
+
 struct ContextMenuState {
     active_path: Mutex<Option<String>>,
     active_tab_id: Mutex<Option<String>>,
@@ -306,6 +584,11 @@ struct ContextMenuState {
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(code) = cli::run_headless(&args) {
+        std::process::exit(code);
+    }
+
     #[cfg(target_os = "windows")]
     {
         std::env::set_var(
@@ -322,6 +605,13 @@ pub fn run() {
         .manage(WatcherState {
             watcher: Mutex::new(None),
         })
+        .manage(SettingsState {
+            settings: Mutex::new(Settings::load()),
+        })
+        .manage(BlockCacheState {
+            caches: Mutex::new(HashMap::new()),
+        })
+        .manage(RenderQueue::default())
         .manage(ContextMenuState {
             active_path: Mutex::new(None),
             active_tab_id: Mutex::new(None),
@@ -344,7 +634,7 @@ pub fn run() {
              let state = app.state::<ContextMenuState>();
 
              match id {
-                 "ctx_open_folder" | "ctx_edit" | "ctx_close" => {
+                 "ctx_open_folder" | "ctx_edit" | "ctx_export_html" | "ctx_close" => {
                     let path_lock = state.active_path.lock().unwrap();
                     if let Some(path) = path_lock.as_ref() {
                         match id {
@@ -354,6 +644,11 @@ pub fn run() {
                                     let _ = window.emit("menu-edit-file", ());
                                 }
                             }
+                            "ctx_export_html" => {
+                                if let Some(window) = app.get_webview_window("main") {
+                                    let _ = window.emit("menu-export-html", ());
+                                }
+                            }
                             "ctx_close" => {
                                 if let Some(window) = app.get_webview_window("main") {
                                     let _ = window.emit("menu-close-file", ());
@@ -363,6 +658,47 @@ pub fn run() {
                         }
                     }
                  }
+                 "ctx_trust_folder" => {
+                    let path = state.active_path.lock().unwrap().clone();
+                    if let Some(path) = path {
+                        let trusted = app.state::<SettingsState>().settings.lock().unwrap().is_trusted(Path::new(&path));
+                        let _ = set_folder_trust(app.clone(), path, !trusted);
+                    }
+                 }
+                 "ctx_dialect_file_default" => {
+                    let path = state.active_path.lock().unwrap().clone();
+                    if let Some(path) = path {
+                        let _ = update_settings(app, |settings| settings.set_file_dialect(Path::new(&path), None));
+                    }
+                 }
+                 _ if id.starts_with("ctx_dialect_file_") => {
+                    let path = state.active_path.lock().unwrap().clone();
+                    if let (Some(path), Some(dialect)) = (path, Dialect::from_id(&id["ctx_dialect_file_".len()..])) {
+                        let _ = update_settings(app, |settings| settings.set_file_dialect(Path::new(&path), Some(dialect)));
+                    }
+                 }
+                 _ if id.starts_with("ctx_dialect_default_") => {
+                    if let Some(dialect) = Dialect::from_id(&id["ctx_dialect_default_".len()..]) {
+                        let _ = update_settings(app, |settings| settings.dialect = dialect);
+                    }
+                 }
+                 "ctx_superscript" => {
+                    let _ = update_settings(app, |settings| settings.superscript = !settings.superscript);
+                 }
+                 "ctx_subscript" => {
+                    let _ = update_settings(app, |settings| settings.subscript = !settings.subscript);
+                 }
+                 "ctx_show_backups" => {
+                    if let Some(dir) = files::backups_dir() {
+                        let _ = fs::create_dir_all(&dir);
+                        let _ = opener::open(dir);
+                    }
+                 }
+                 _ if id.starts_with("ctx_backups_") => {
+                    if let Some(backups) = files::Backups::from_id(&id["ctx_backups_".len()..]) {
+                        let _ = update_settings(app, |settings| settings.backups = backups);
+                    }
+                 }
                  "ctx_tab_rename" => {
                     let tab_lock = state.active_tab_id.lock().unwrap();
                     if let Some(tab_id) = tab_lock.as_ref() {
@@ -458,6 +794,17 @@ pub fn run() {
         .invoke_handler(tauri::generate_handler![
             open_markdown,
             render_markdown,
+            render_markdown_blocks,
+            release_block_cache,
+            document_info,
+            document_outline,
+            check_links,
+            export_html,
+            get_dialect_settings,
+            set_default_dialect,
+            set_file_dialect,
+            is_path_trusted,
+            set_folder_trust,
             send_markdown_path,
             read_file_content,
             save_file_content,
@@ -0,0 +1,444 @@
+use comrak::nodes::{AstNode, NodeValue};
+use comrak::{parse_document, Anchorizer, Arena};
+use percent_encoding::percent_decode_str;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use crate::analysis::{self, Heading};
+use crate::dialect::{Dialect, DialectProfile};
+use crate::embeds;
+use crate::files;
+use crate::frontmatter;
+use crate::links;
+use crate::markdown;
+use crate::wikilinks::{self, WIKILINK};
+
+static HTML_ID: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"\b(?:id|name)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap());
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Problem {
+    /// A link or wiki-link to a file that doesn't exist.
+    MissingFile,
+    /// A `#fragment` matching no heading or anchor in its target.
+    MissingAnchor,
+    MissingImage,
+    /// A `![[...]]` whose note, section or file can't be found.
+    UnresolvedEmbed,
+}
+
+/// A link, image or embed pointing nowhere. Lines and columns are 1-based
+/// and count characters, like an editor's; `end_column` is exclusive.
+#[derive(Serialize, Clone, Debug)]
+pub struct BrokenLink {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+    pub problem: Problem,
+    pub target: String,
+    pub message: String,
+}
+
+/// Checks the links, images, wiki-links and embeds of one document. Relative
+/// targets are looked up next to `path`; without a path, only `#anchors`
+/// within the document are checked. `dialect_for` gives the dialect of the
+/// notes it links to, whose headings `#fragments` are checked against.
+pub fn check_document(
+    content: &str,
+    path: Option<&Path>,
+    dialect: Dialect,
+    dialect_for: &dyn Fn(&Path) -> Dialect,
+) -> Vec<BrokenLink> {
+    Checker::new(dialect_for).check(content, path, dialect)
+}
+
+/// Checks every Markdown file under `dir`, skipping hidden folders.
+pub fn check_folder(dir: &Path, dialect_for: &dyn Fn(&Path) -> Dialect) -> Result<Vec<BrokenLink>, String> {
+    let mut files = Vec::new();
+    collect_markdown_files(dir, &mut files).map_err(|e| e.to_string())?;
+    files.sort();
+
+    let mut checker = Checker::new(dialect_for);
+    let mut broken = Vec::new();
+    for file in files {
+        let content = files::read_to_string(&file).map_err(|e| format!("failed to read {}: {}", file.display(), e))?;
+        broken.extend(checker.check(&content, Some(&file), dialect_for(&file)));
+    }
+    Ok(broken)
+}
+
+fn collect_markdown_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.')) {
+            continue;
+        }
+        if path.is_dir() {
+            collect_markdown_files(&path, files)?;
+        } else if is_markdown(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Checks documents one after another, remembering the notes it has read
+/// for `#fragment` lookups.
+struct Checker<'d> {
+    dialect_for: &'d dyn Fn(&Path) -> Dialect,
+    notes: HashMap<PathBuf, Option<String>>,
+    anchors: HashMap<PathBuf, Option<HashSet<String>>>,
+}
+
+/// Where a reference was found and what it points to.
+struct Found<'s> {
+    start: (usize, usize),
+    end: (usize, usize),
+    target: &'s str,
+}
+
+impl<'d> Checker<'d> {
+    fn new(dialect_for: &'d dyn Fn(&Path) -> Dialect) -> Self {
+        Checker { dialect_for, notes: HashMap::new(), anchors: HashMap::new() }
+    }
+
+    fn check(&mut self, content: &str, path: Option<&Path>, dialect: Dialect) -> Vec<BrokenLink> {
+        let profile = dialect.profile();
+        let base_dir = path.and_then(Path::parent);
+        let file = path.map(|p| p.display().to_string()).unwrap_or_default();
+        let (_, content) = frontmatter::extract(content);
+        let lines: Vec<&str> = content.split('\n').collect();
+
+        // Image paths with spaces are parsed the way the renderer parses them
+        let processed = markdown::map_outside_code(&content, &markdown::comrak_options(&profile, false), markdown::wrap_image_paths);
+        let processed_lines: Vec<&str> = processed.split('\n').collect();
+        let unwrapped = |(line, byte): (usize, usize)| {
+            let original = lines.get(line.wrapping_sub(1)).copied().unwrap_or("");
+            let processed = processed_lines.get(line.wrapping_sub(1)).copied().unwrap_or("");
+            (line, original_column(original, processed, byte))
+        };
+
+        let arena = Arena::new();
+        let root = parse_document(&arena, &processed, &markdown::comrak_options(&profile, true));
+        let own_anchors = anchors(root, &profile);
+
+        let mut broken = Vec::new();
+        let mut report = |found: &Found, (problem, message): (Problem, String)| {
+            let column = |(line, byte): (usize, usize)| char_column(lines.get(line.wrapping_sub(1)).copied().unwrap_or(""), byte);
+            broken.push(BrokenLink {
+                file: file.clone(),
+                line: found.start.0,
+                column: column(found.start),
+                end_line: found.end.0,
+                end_column: column(found.end),
+                problem,
+                target: found.target.to_string(),
+                message,
+            });
+        };
+
+        for node in root.descendants() {
+            let pos = analysis::position(node);
+            let references = match &node.data.borrow().value {
+                NodeValue::Link(link) => vec![(link.url.clone(), false)],
+                NodeValue::Image(link) => vec![(link.url.clone(), true)],
+                NodeValue::HtmlInline(html) => analysis::html_references(html),
+                NodeValue::HtmlBlock(block) => analysis::html_references(&block.literal),
+                _ => continue,
+            };
+            for (url, is_image) in references {
+                let found = Found {
+                    start: unwrapped((pos.start.line, pos.start.column)),
+                    end: unwrapped((pos.end.line, pos.end.column + 1)),
+                    target: &url,
+                };
+                if let Some(problem) = self.check_url(&url, is_image, base_dir, &own_anchors) {
+                    report(&found, problem);
+                }
+            }
+        }
+
+        if profile.wikilinks || profile.embeds {
+            // The same code the renderer leaves alone, as comrak parses it
+            let code = markdown::code_ranges(&content, &markdown::comrak_options(&profile, false));
+            let line_starts: Vec<usize> = std::iter::once(0).chain(content.match_indices('\n').map(|(i, _)| i + 1)).collect();
+            let at = |offset: usize| {
+                let line = line_starts.partition_point(|&start| start <= offset);
+                (line, offset - line_starts[line - 1] + 1)
+            };
+
+            for caps in WIKILINK.captures_iter(&content) {
+                let embed = &caps[1] == "!";
+                if (embed && !profile.embeds) || (!embed && !profile.wikilinks) {
+                    continue;
+                }
+                let whole = caps.get(0).unwrap();
+                if code.iter().any(|range| range.start < whole.end() && whole.start() < range.end) {
+                    continue;
+                }
+                let found = Found { start: at(whole.start()), end: at(whole.end()), target: caps.get(2).unwrap().as_str() };
+                if let Some(problem) = self.check_wikilink(found.target, embed, base_dir, &own_anchors) {
+                    report(&found, problem);
+                }
+            }
+        }
+
+        broken.sort_by_key(|b| (b.line, b.column));
+        broken
+    }
+
+    fn check_url(&mut self, url: &str, is_image: bool, base_dir: Option<&Path>, own_anchors: &HashSet<String>) -> Option<(Problem, String)> {
+        if let Some(anchor) = url.strip_prefix('#') {
+            let anchor = percent_decode_str(anchor).decode_utf8_lossy();
+            return (!anchor.is_empty() && !own_anchors.contains(anchor.as_ref()))
+                .then(|| (Problem::MissingAnchor, format!("No heading or anchor '#{}' in this document", anchor)));
+        }
+
+        let path = links::local_path(url, base_dir?)?;
+        if !path.exists() {
+            return Some(if is_image {
+                (Problem::MissingImage, format!("Image not found: {}", path.display()))
+            } else {
+                (Problem::MissingFile, format!("File not found: {}", path.display()))
+            });
+        }
+
+        let fragment = url.split_once('#').map(|(_, f)| percent_decode_str(f).decode_utf8_lossy()).filter(|f| !f.is_empty())?;
+        if is_image || !is_markdown(&path) {
+            return None;
+        }
+        let anchors = self.anchors_of(&path)?;
+        (!anchors.contains(fragment.as_ref()))
+            .then(|| (Problem::MissingAnchor, format!("No heading or anchor '#{}' in {}", fragment, path.display())))
+    }
+
+    /// `[[Note#Heading]]` or `![[target]]`, without the brackets.
+    fn check_wikilink(&mut self, inner: &str, embed: bool, base_dir: Option<&Path>, own_anchors: &HashSet<String>) -> Option<(Problem, String)> {
+        let target = inner.split('|').next().unwrap_or("").trim_end_matches('\\').trim();
+        let (note, fragment) = match target.split_once('#') {
+            Some((note, fragment)) => (note.trim(), Some(fragment.trim()).filter(|f| !f.is_empty())),
+            None => (target, None),
+        };
+        let missing = if embed { Problem::UnresolvedEmbed } else { Problem::MissingFile };
+
+        if note.is_empty() {
+            let heading = fragment?;
+            let slug = Anchorizer::new().anchorize(heading.to_string());
+            return (!embed && !own_anchors.contains(&slug))
+                .then(|| (Problem::MissingAnchor, format!("No heading '{}' in this document", heading)));
+        }
+        let dir = base_dir?;
+
+        // Embedded images and other attachments sit next to the note
+        if embed && embeds::note_target(note).is_none() {
+            let path = links::local_path(note, dir)?;
+            return (!path.exists()).then(|| (missing, format!("Embedded file not found: {}", path.display())));
+        }
+
+        let Some(relative) = wikilinks::resolve_note(dir, note) else {
+            return Some((missing, format!("Note not found: {}", note)));
+        };
+        let fragment = fragment?;
+        let content = self.read_note(&dir.join(relative))?;
+        if embeds::extract_fragment(&content, fragment).is_some() {
+            return None;
+        }
+        let problem = if embed { Problem::UnresolvedEmbed } else { Problem::MissingAnchor };
+        Some((problem, format!("No section '{}' in {}", fragment, note)))
+    }
+
+    fn read_note(&mut self, path: &Path) -> Option<String> {
+        self.notes.entry(path.to_path_buf()).or_insert_with(|| files::read_to_string(path).ok()).clone()
+    }
+
+    fn anchors_of(&mut self, path: &Path) -> Option<HashSet<String>> {
+        if let Some(anchors) = self.anchors.get(path) {
+            return anchors.clone();
+        }
+        let anchors = self.read_note(path).map(|content| {
+            let profile = (self.dialect_for)(path).profile();
+            let (_, content) = frontmatter::extract(&content);
+            let arena = Arena::new();
+            anchors(parse_document(&arena, &content, &markdown::comrak_options(&profile, false)), &profile)
+        });
+        self.anchors.insert(path.to_path_buf(), anchors.clone());
+        anchors
+    }
+}
+
+/// Every id a `#fragment` can point at: heading ids (when the dialect gives
+/// headings ids), footnotes, and `id`/`name` attributes in raw HTML.
+fn anchors<'a>(root: &'a AstNode<'a>, profile: &DialectProfile) -> HashSet<String> {
+    let mut anchors = HashSet::new();
+    if profile.header_ids {
+        add_slugs(&analysis::heading_tree(root), &mut anchors);
+    }
+
+    let mut footnotes = 0;
+    for node in root.descendants() {
+        match &node.data.borrow().value {
+            NodeValue::FootnoteDefinition(_) => footnotes += 1,
+            NodeValue::HtmlInline(html) => add_html_ids(html, &mut anchors),
+            NodeValue::HtmlBlock(block) => add_html_ids(&block.literal, &mut anchors),
+            _ => {}
+        }
+    }
+    for n in 1..=footnotes {
+        anchors.insert(format!("fn-{}", n));
+        anchors.insert(format!("fnref-{}", n));
+    }
+    anchors
+}
+
+fn add_slugs(headings: &[Heading], anchors: &mut HashSet<String>) {
+    for heading in headings {
+        anchors.insert(heading.slug.clone());
+        add_slugs(&heading.children, anchors);
+    }
+}
+
+fn add_html_ids(html: &str, anchors: &mut HashSet<String>) {
+    for caps in HTML_ID.captures_iter(html) {
+        if let Some(id) = caps.get(1).or_else(|| caps.get(2)) {
+            anchors.insert(id.as_str().to_string());
+        }
+    }
+}
+
+/// Maps a 1-based byte column of `processed`, which is `original` with the
+/// brackets `wrap_image_paths` adds, back to `original`.
+fn original_column(original: &str, processed: &str, byte: usize) -> usize {
+    if original.len() == processed.len() {
+        return byte;
+    }
+    let (original, processed) = (original.as_bytes(), processed.as_bytes());
+    let mut i = 0;
+    for &b in &processed[..byte.saturating_sub(1).min(processed.len())] {
+        if original.get(i) == Some(&b) {
+            i += 1;
+        }
+    }
+    i + 1
+}
+
+/// Turns a 1-based byte column into a 1-based character column.
+fn char_column(line: &str, byte: usize) -> usize {
+    let byte = byte.saturating_sub(1).min(line.len());
+    let prefix = line.get(..byte).unwrap_or(line);
+    prefix.chars().count() + 1
+}
+
+fn is_markdown(path: &Path) -> bool {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .is_some_and(|ext| matches!(ext.as_str(), "md" | "markdown" | "mdown" | "mkd"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    fn check(content: &str) -> Vec<(usize, usize, usize, Problem, String)> {
+        check_document(content, None, Dialect::Obsidian, &|_| Dialect::Obsidian)
+            .into_iter()
+            .map(|b| (b.line, b.column, b.end_column, b.problem, b.target))
+            .collect()
+    }
+
+    /// A folder of notes to link between.
+    fn vault(files: &[(&str, &str)]) -> TempDir {
+        let dir = TempDir::new();
+        for (path, content) in files {
+            dir.write(path, content);
+        }
+        dir
+    }
+
+    fn check_file(vault: &TempDir, file: &str) -> Vec<(usize, Problem, String)> {
+        let path = vault.path().join(file);
+        let content = fs::read_to_string(&path).unwrap();
+        check_document(&content, Some(&path), Dialect::Obsidian, &|_| Dialect::Obsidian)
+            .into_iter()
+            .map(|b| (b.line, b.problem, b.target))
+            .collect()
+    }
+
+    fn problems(list: &[(usize, Problem, &str)]) -> Vec<(usize, Problem, String)> {
+        list.iter().map(|(line, problem, target)| (*line, *problem, target.to_string())).collect()
+    }
+
+    #[test]
+    fn skips_wikilinks_in_code() {
+        let content = "[[#Nope]]\n\n~~~~\n```\n[[#Fence]]\n~~~~\n\n    [[#Indented]]\n\n`[[#Span]]` é [[#Also]]\n\n<pre>\n[[#Html]]\n</pre>\n";
+        assert_eq!(
+            check(content),
+            vec![
+                (1, 1, 10, Problem::MissingAnchor, "#Nope".to_string()),
+                (10, 15, 24, Problem::MissingAnchor, "#Also".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_anchors_within_the_document() {
+        let content = "# Set Up\n\n[ok](#set-up) [bad](#tear-down) [note](#fn-1)[^1]\n\n<a id=\"custom\"></a>[html](#custom)\n\n[^1]: Note.\n";
+        assert_eq!(check(content), vec![(3, 15, 32, Problem::MissingAnchor, "#tear-down".to_string())]);
+    }
+
+    #[test]
+    fn checks_links_images_and_embeds_against_the_folder() {
+        let vault = vault(&[
+            ("index.md", "[a](other.md) [b](missing.md) [c](other.md#intro) [d](other.md#nope)\n\n![img](pic.png) ![gone](gone.png)\n\n[[Other]] [[Nowhere]] [[Other#Intro]] [[Other#Outro]]\n\n![[Other]]\n\n![[Absent]]\n\n![[pic.png]] ![[lost.png]]\n\n[site](https://example.com/x.md)\n"),
+            ("other.md", "# Intro\n\ntext\n"),
+            ("pic.png", ""),
+        ]);
+
+        assert_eq!(
+            check_file(&vault, "index.md"),
+            problems(&[
+                (1, Problem::MissingFile, "missing.md"),
+                (1, Problem::MissingAnchor, "other.md#nope"),
+                (3, Problem::MissingImage, "gone.png"),
+                (5, Problem::MissingFile, "Nowhere"),
+                (5, Problem::MissingAnchor, "Other#Outro"),
+                (9, Problem::UnresolvedEmbed, "Absent"),
+                (11, Problem::UnresolvedEmbed, "lost.png"),
+            ])
+        );
+    }
+
+    #[test]
+    fn checks_image_paths_with_spaces_like_the_renderer() {
+        let vault = vault(&[("index.md", "![a](my image.png) ![b](here.png) [c](gone.md)\n"), ("here.png", "")]);
+        assert_eq!(check_file(&vault, "index.md"), problems(&[(1, Problem::MissingImage, "my image.png"), (1, Problem::MissingFile, "gone.md")]));
+
+        let path = vault.path().join("index.md");
+        let content = fs::read_to_string(&path).unwrap();
+        let columns: Vec<_> = check_document(&content, Some(&path), Dialect::Obsidian, &|_| Dialect::Obsidian)
+            .into_iter()
+            .map(|b| (b.column, b.end_column))
+            .collect();
+        assert_eq!(columns, [(1, 19), (35, 47)]);
+    }
+
+    #[test]
+    fn checks_every_note_in_a_folder_except_hidden_ones() {
+        let vault = vault(&[
+            ("a.md", "[x](nope.md)\n"),
+            ("sub/b.markdown", "[[Missing]]\n"),
+            (".hidden/c.md", "[x](nope.md)\n"),
+            ("notes.txt", "[x](nope.md)\n"),
+        ]);
+
+        let broken = check_folder(vault.path(), &|_| Dialect::Obsidian).unwrap();
+        let files: Vec<_> = broken.iter().map(|b| Path::new(&b.file).strip_prefix(vault.path()).unwrap().to_path_buf()).collect();
+        assert_eq!(files, [PathBuf::from("a.md"), PathBuf::from("sub/b.markdown")]);
+    }
+}
@@ -0,0 +1,250 @@
+use comrak::nodes::{Ast, AstNode, NodeValue};
+use comrak::Arena;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use regex::{Captures, Regex};
+use std::cell::RefCell;
+use std::path::{Component, Path, PathBuf};
+use std::sync::LazyLock;
+
+use crate::analysis::plain_text;
+use crate::markdown::{escape_html, unescape_html};
+
+static IMG_SRC: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"(<img\b[^>]*?\bsrc\s*=\s*)(?:"([^"]*)"|'([^']*)')"#).unwrap());
+
+/// What JavaScript's `encodeURIComponent` leaves alone, which is how Tauri's
+/// `convertFileSrc` encodes the path.
+const URI_COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'!')
+    .remove(b'~')
+    .remove(b'*')
+    .remove(b'\'')
+    .remove(b'(')
+    .remove(b')');
+
+/// Points relative and `file://` images at the files they name, as `asset:`
+/// URLs the viewer's webview can load. `<img>` tags in raw HTML (which
+/// includes `![[...]]` image embeds) are resolved too. Links keep their
+/// target as written, like wiki-links, for the viewer to open itself. Links
+/// and images whose file doesn't exist get the `missing-target` class.
+pub fn resolve<'a>(arena: &'a Arena<AstNode<'a>>, root: &'a AstNode<'a>, base_dir: &Path) {
+    let nodes: Vec<_> = root.descendants().collect();
+    for node in nodes {
+        let mut ast = node.data.borrow_mut();
+        match &mut ast.value {
+            NodeValue::Link(link) | NodeValue::Image(link) => {
+                let Some(path) = local_path(&link.url, base_dir) else {
+                    continue;
+                };
+                let is_image = matches!(ast.value, NodeValue::Image(_));
+                if let NodeValue::Image(image) = &mut ast.value {
+                    image.url = asset_url(&path, None);
+                }
+                if path.exists() {
+                    continue;
+                }
+                drop(ast);
+                mark_missing(arena, node, is_image);
+            }
+            NodeValue::HtmlInline(html) => {
+                *html = resolve_img_srcs(html, base_dir);
+            }
+            NodeValue::HtmlBlock(block) => {
+                block.literal = resolve_img_srcs(&block.literal, base_dir);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The file a link or image URL refers to, for relative paths, absolute
+/// paths and `file://` URLs; `None` for anything else. `..` is resolved and
+/// percent-escapes are decoded. Doesn't check that the file exists.
+pub fn local_path(url: &str, base_dir: &Path) -> Option<PathBuf> {
+    if url.is_empty() || url.starts_with('#') || url.starts_with("//") {
+        return None;
+    }
+
+    let path_part = match url.get(..7) {
+        // file:///C:/x is C:/x on Windows and /x elsewhere
+        Some(scheme) if scheme.eq_ignore_ascii_case("file://") => {
+            if cfg!(windows) { url[7..].trim_start_matches('/') } else { &url[7..] }
+        }
+        _ if has_scheme(url) => return None,
+        _ => url,
+    };
+
+    let path_part = path_part.split(['?', '#']).next().unwrap_or("");
+    if path_part.is_empty() {
+        return None;
+    }
+    let decoded = percent_decode_str(path_part).decode_utf8_lossy();
+    let path = Path::new(decoded.as_ref());
+    Some(normalize(&if path.is_absolute() { path.to_path_buf() } else { base_dir.join(path) }))
+}
+
+/// The URL Tauri's `convertFileSrc` would give `path`.
+pub fn asset_url(path: &Path, fragment: Option<&str>) -> String {
+    let base = if cfg!(any(windows, target_os = "android")) { "http://asset.localhost/" } else { "asset://localhost/" };
+    let mut url = format!("{}{}", base, utf8_percent_encode(&path.to_string_lossy(), URI_COMPONENT));
+    if let Some(fragment) = fragment {
+        url.push('#');
+        url.push_str(fragment);
+    }
+    url
+}
+
+/// Calls `f` with the unescaped `src` of every `<img>` tag in `html`, and
+/// puts back what it returns, if anything.
+pub fn map_img_srcs(html: &str, mut f: impl FnMut(&str) -> Option<String>) -> String {
+    IMG_SRC.replace_all(html, |caps: &Captures| {
+        let src = caps.get(2).or_else(|| caps.get(3)).map(|m| m.as_str()).unwrap_or("");
+        match f(&unescape_html(src)) {
+            Some(src) => format!("{}\"{}\"", &caps[1], escape_html(&src)),
+            None => caps[0].to_string(),
+        }
+    })
+    .into_owned()
+}
+
+fn resolve_img_srcs(html: &str, base_dir: &Path) -> String {
+    if !html.contains("<img") {
+        return html.to_string();
+    }
+    map_img_srcs(html, |src| local_path(src, base_dir).map(|path| asset_url(&path, None)))
+}
+
+/// Replaces a link or image whose target is missing with the same element
+/// written as HTML, so it can carry a class. A link keeps its content.
+fn mark_missing<'a>(arena: &'a Arena<AstNode<'a>>, node: &'a AstNode<'a>, is_image: bool) {
+    let (url, title) = match &node.data.borrow().value {
+        NodeValue::Link(link) | NodeValue::Image(link) => (link.url.clone(), link.title.clone()),
+        _ => return,
+    };
+    let title = if title.is_empty() { String::new() } else { format!(" title=\"{}\"", escape_html(&title)) };
+
+    if is_image {
+        let html = format!(
+            "<img src=\"{}\" alt=\"{}\"{} class=\"missing-target\" />",
+            escape_html(&url),
+            escape_html(&plain_text(node)),
+            title
+        );
+        node.insert_before(html_inline(arena, html));
+    } else {
+        node.insert_before(html_inline(arena, format!("<a href=\"{}\"{} class=\"missing-target\">", escape_html(&url), title)));
+        for child in node.children().collect::<Vec<_>>() {
+            node.insert_before(child);
+        }
+        node.insert_before(html_inline(arena, "</a>".to_string()));
+    }
+    node.detach();
+}
+
+fn html_inline<'a>(arena: &'a Arena<AstNode<'a>>, html: String) -> &'a AstNode<'a> {
+    arena.alloc(AstNode::new(RefCell::new(Ast::new(NodeValue::HtmlInline(html), (0, 0).into()))))
+}
+
+/// `mailto:`, `https:` and the like, but not a Windows drive letter.
+fn has_scheme(url: &str) -> bool {
+    let Some((scheme, _)) = url.split_once(':') else {
+        return false;
+    };
+    scheme.len() > 1
+        && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+}
+
+/// Resolves `.` and `..` without touching the file system, so links to
+/// files that don't exist still come out as a clean path.
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !out.pop() {
+                    out.push(component);
+                }
+            }
+            _ => out.push(component),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::Dialect;
+    use crate::markdown;
+    use crate::test_support::TempDir;
+    use comrak::{format_html, parse_document};
+
+    #[test]
+    fn finds_local_paths() {
+        let base = Path::new("/notes/sub");
+        assert_eq!(local_path("a.png", base), Some(PathBuf::from("/notes/sub/a.png")));
+        assert_eq!(local_path("../img/a%20b.png?v=2#x", base), Some(PathBuf::from("/notes/img/a b.png")));
+        assert_eq!(local_path("./a.md#intro", base), Some(PathBuf::from("/notes/sub/a.md")));
+        for url in ["", "#intro", "//cdn.example.com/a.png", "https://example.com/a.md", "mailto:me@example.com", "?q"] {
+            assert_eq!(local_path(url, base), None, "{:?}", url);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn finds_absolute_and_file_url_paths() {
+        let base = Path::new("/notes");
+        assert_eq!(local_path("/etc/a.png", base), Some(PathBuf::from("/etc/a.png")));
+        assert_eq!(local_path("file:///tmp/a%23b.md", base), Some(PathBuf::from("/tmp/a#b.md")));
+        assert_eq!(local_path("FILE:///tmp/x/../a.md", base), Some(PathBuf::from("/tmp/a.md")));
+    }
+
+    #[test]
+    fn tells_schemes_from_drive_letters() {
+        assert!(has_scheme("https://example.com"));
+        assert!(has_scheme("x-custom+app:thing"));
+        assert!(!has_scheme("C:/notes/a.md"));
+        assert!(!has_scheme("notes/a.md"));
+    }
+
+    #[test]
+    fn encodes_asset_urls_like_convert_file_src() {
+        let url = asset_url(Path::new("/a b/c(1)#.png"), Some("top"));
+        assert!(url.ends_with("%2Fa%20b%2Fc(1)%23.png#top"), "{}", url);
+    }
+
+    #[test]
+    fn rewrites_img_srcs_in_html() {
+        let html = map_img_srcs(r#"<img alt="x" src='a&amp;b.png'><img src="keep.png">"#, |src| (src == "a&b.png").then(|| "new\"src".to_string()));
+        assert_eq!(html, r#"<img alt="x" src="new&quot;src"><img src="keep.png">"#);
+    }
+
+    #[test]
+    fn resolves_links_and_marks_missing_targets() {
+        let temp = TempDir::new();
+        let dir = temp.path();
+        temp.write("here.png", "");
+
+        let options = markdown::comrak_options(&Dialect::Gfm.profile(), false);
+        let arena = Arena::new();
+        let root = parse_document(&arena, "![ok](here.png) ![gone](gone.png \"T\") [doc](gone.md) [note](here.png#top) [web](https://example.com)\n", &options);
+        resolve(&arena, root, dir);
+        let mut html = Vec::new();
+        format_html(root, &options, &mut html).unwrap();
+        let html = String::from_utf8(html).unwrap();
+
+        let here = asset_url(&dir.join("here.png"), None);
+        let gone = asset_url(&dir.join("gone.png"), None);
+        assert_eq!(
+            html,
+            format!(
+                "<p><img src=\"{}\" alt=\"ok\" /> <img src=\"{}\" alt=\"gone\" title=\"T\" class=\"missing-target\" /> <a href=\"gone.md\" class=\"missing-target\">doc</a> <a href=\"here.png#top\">note</a> <a href=\"https://example.com\">web</a></p>\n",
+                here, gone
+            )
+        );
+    }
+}
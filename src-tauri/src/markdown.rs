@@ -0,0 +1,447 @@
+use comrak::nodes::NodeValue;
+use comrak::{format_html_with_plugins, parse_document, Arena, ComrakExtensionOptions, ComrakOptions, ComrakPlugins};
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use crate::callouts;
+use crate::dialect::{Dialect, DialectProfile};
+use crate::embeds::Embeds;
+use crate::extensions;
+use crate::frontmatter::{self, FrontMatter};
+use crate::highlight::ClassHighlighter;
+use crate::links;
+use crate::math::Math;
+use crate::sanitize::sanitize_html;
+use crate::toc;
+use crate::wikilinks;
+
+static IMAGE_TITLE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"^(.*?)(\s+(?:"[^"]*"|'[^']*'))?\s*$"#).unwrap());
+
+/// Lets image paths contain spaces, as people write them for local files:
+/// `![](my image.png)` becomes `![](<my image.png>)`, which CommonMark
+/// accepts. Parentheses in the path are paired up the way the parser does.
+pub fn wrap_image_paths(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find("![") {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(open) = rest.find(['\n', ']']).filter(|&i| rest[i..].starts_with("](")).map(|i| i + 2) else {
+            out.push_str("![");
+            rest = &rest[2..];
+            continue;
+        };
+        let Some(close) = destination_end(&rest[open..]).map(|i| open + i) else {
+            out.push_str(&rest[..open]);
+            rest = &rest[open..];
+            continue;
+        };
+
+        let destination = &rest[open..close];
+        let caps = IMAGE_TITLE.captures(destination).unwrap();
+        let path = caps[1].trim();
+        out.push_str(&rest[..open]);
+        if path.contains(char::is_whitespace) && !path.contains(['<', '>']) {
+            out.push_str(&format!("<{}>{}", path, caps.get(2).map_or("", |m| m.as_str())));
+        } else {
+            out.push_str(destination);
+        }
+        rest = &rest[close..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// Where the `)` closing a link destination is, skipping balanced pairs and
+/// backslash escapes. `None` if the line ends first.
+fn destination_end(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '(' => depth += 1,
+            ')' if depth == 0 => return Some(i),
+            ')' => depth -= 1,
+            '\n' => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Escapes text for use in HTML element content or a quoted attribute value.
+pub fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Reverses `escape_html`, for attribute values read back out of HTML.
+pub fn unescape_html(text: &str) -> String {
+    text.replace("&quot;", "\"").replace("&#39;", "'").replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&")
+}
+
+/// Applies `f` to every part of `content` that is outside code, copying the
+/// code itself through untouched. What counts as code is what comrak parses
+/// as code with `options` (see `code_ranges`).
+pub fn map_outside_code(content: &str, options: &ComrakOptions, mut f: impl FnMut(&str) -> String) -> String {
+    let mut out = String::with_capacity(content.len());
+    let mut plain_start = 0;
+    for range in code_ranges(content, options) {
+        out.push_str(&f(&content[plain_start..range.start]));
+        out.push_str(&content[range.clone()]);
+        plain_start = range.end;
+    }
+    out.push_str(&f(&content[plain_start..]));
+    out
+}
+
+/// Byte ranges of `content` that comrak parses as code: fenced and indented
+/// code blocks, HTML blocks and inline code spans, in order.
+///
+/// Block boundaries come from the AST. comrak 0.18's end positions are
+/// unreliable (HTML blocks end a line early) and inline nodes have none worth
+/// using, so a code block runs until the next block starts, and code spans
+/// are matched within their block against the backtick runs comrak found.
+pub fn code_ranges(content: &str, options: &ComrakOptions) -> Vec<Range<usize>> {
+    let arena = Arena::new();
+    let root = parse_document(&arena, content, options);
+
+    let mut line_starts: Vec<usize> = std::iter::once(0)
+        .chain(content.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+    if line_starts.last() != Some(&content.len()) {
+        line_starts.push(content.len());
+    }
+    let offset = |line: usize| line_starts[(line - 1).min(line_starts.len() - 1)];
+
+    let mut starts = BTreeSet::new();
+    let mut code_blocks = Vec::new();
+    let mut spans: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for node in root.descendants().skip(1) {
+        let ast = node.data.borrow();
+        let line = ast.sourcepos.start.line;
+        match &ast.value {
+            NodeValue::CodeBlock(_) | NodeValue::HtmlBlock(_) => code_blocks.push(line),
+            NodeValue::Code(code) => {
+                let block = node.ancestors().find(|n| n.data.borrow().value.block());
+                if let Some(block) = block {
+                    let line = block.data.borrow().sourcepos.start.line;
+                    spans.entry(line).or_default().push(code.num_backticks);
+                }
+            }
+            _ => {}
+        }
+        if ast.value.block() && line > 0 {
+            starts.insert(line);
+        }
+    }
+    let block_end = |line: usize| starts.range(line + 1..).next().map_or(content.len(), |&next| offset(next));
+
+    let mut ranges: Vec<Range<usize>> = code_blocks.into_iter().map(|line| offset(line)..block_end(line)).collect();
+    for (line, ticks) in spans {
+        let start = offset(line);
+        let end = block_end(line);
+        ranges.extend(code_spans(&content[start..end], &ticks).map(|r| r.start + start..r.end + start));
+    }
+    ranges.sort_by_key(|r| r.start);
+    ranges
+}
+
+/// Finds the code spans in one block's text, given the length of each span's
+/// backtick run as comrak parsed them. A run that doesn't match the next
+/// expected span, or is escaped, is ordinary text.
+fn code_spans<'a>(text: &'a str, ticks: &'a [usize]) -> impl Iterator<Item = Range<usize>> + 'a {
+    let bytes = text.as_bytes();
+    let mut expected = ticks.iter().peekable();
+    let mut i = 0;
+
+    std::iter::from_fn(move || {
+        while i < bytes.len() {
+            let &&want = expected.peek()?;
+            match bytes[i] {
+                b'\\' => i += 2,
+                b'`' => {
+                    let run_start = i;
+                    while i < bytes.len() && bytes[i] == b'`' {
+                        i += 1;
+                    }
+                    if i - run_start != want {
+                        continue;
+                    }
+
+                    // Backslashes are literal inside a span, so the closing
+                    // run is just the next one of the same length
+                    let mut j = i;
+                    while j < bytes.len() {
+                        if bytes[j] != b'`' {
+                            j += 1;
+                            continue;
+                        }
+                        let close_start = j;
+                        while j < bytes.len() && bytes[j] == b'`' {
+                            j += 1;
+                        }
+                        if j - close_start == want {
+                            expected.next();
+                            i = j;
+                            return Some(run_start..j);
+                        }
+                    }
+                }
+                _ => i += 1,
+            }
+        }
+        None
+    })
+}
+
+#[derive(Serialize)]
+pub struct RenderedMarkdown {
+    pub html: String,
+    pub front_matter: Option<FrontMatter>,
+}
+
+/// How a document is rendered.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct RenderOptions {
+    pub dialect: Dialect,
+    /// Pass the output through the HTML allowlist, for files from folders
+    /// the user hasn't marked as trusted.
+    pub sanitize: bool,
+    /// Point local images at `asset:` URLs and mark links and images whose
+    /// file is missing (see `links::resolve`). For the viewer; exports keep
+    /// the paths as written.
+    pub asset_urls: bool,
+    /// `^superscript^`, off by default as in GitHub.
+    pub superscript: bool,
+    /// `~subscript~`, off by default since it takes over `~strikethrough~`.
+    pub subscript: bool,
+}
+
+impl RenderOptions {
+    /// The dialect's profile with the user's script settings applied. Strict
+    /// CommonMark stays without extensions.
+    pub fn profile(&self) -> DialectProfile {
+        let profile = self.dialect.profile();
+        if self.dialect == Dialect::Commonmark {
+            return profile;
+        }
+        DialectProfile { superscript: self.superscript, subscript: self.subscript, ..profile }
+    }
+}
+
+pub fn convert_markdown(content: &str, path: Option<&Path>, options: RenderOptions) -> String {
+    render_markdown(content, path, options).html
+}
+
+pub fn render_markdown(content: &str, path: Option<&Path>, options: RenderOptions) -> RenderedMarkdown {
+    let mut stack: Vec<PathBuf> = path.and_then(|p| std::fs::canonicalize(p).ok()).into_iter().collect();
+    render_document(content, path, options, &mut stack)
+}
+
+/// Renders one document. `stack` lists the notes being transcluded into each
+/// other (see `Embeds`); only the outermost document gets `data-sourcepos`
+/// attributes, since the viewer maps those to lines of the open file.
+pub fn render_document(content: &str, path: Option<&Path>, options: RenderOptions, stack: &mut Vec<PathBuf>) -> RenderedMarkdown {
+    let is_nested = stack.len() > 1;
+    let profile = options.profile();
+    let base_dir = path.and_then(|p| p.parent());
+    let (front_matter, content) = frontmatter::extract(content);
+    let content = content.as_str();
+
+    let mut embeds = Embeds::new(base_dir, options, stack);
+    let mut math = Math::new();
+    let processed = preprocess(content, &profile, base_dir, &mut embeds, &mut math);
+
+    let comrak_options = comrak_options(&profile, !is_nested);
+    let arena = Arena::new();
+    let root = parse_document(&arena, &processed, &comrak_options);
+    extensions::apply(&arena, root, &profile);
+    if profile.embeds {
+        embeds.place(&arena, root);
+    }
+    if let Some(base_dir) = base_dir.filter(|_| options.asset_urls) {
+        links::resolve(&arena, root, base_dir);
+    }
+    if profile.callouts {
+        callouts::expand(&arena, root);
+    }
+    if profile.toc {
+        toc::expand(root);
+    }
+
+    let mut html = Vec::new();
+    let mut plugins = ComrakPlugins::default();
+    plugins.render.codefence_syntax_highlighter = Some(&ClassHighlighter);
+    format_html_with_plugins(root, &comrak_options, &mut html, &plugins).unwrap();
+    let mut html = embeds.splice(&math.splice(&String::from_utf8_lossy(&html)));
+
+    // Embedded notes end up inside this document's HTML, so only the
+    // outermost render needs to sanitize
+    if options.sanitize && !is_nested {
+        html = sanitize_html(&html);
+    }
+
+    RenderedMarkdown { html, front_matter }
+}
+
+/// Runs the text passes that happen before parsing (math, `[TOC]` markers,
+/// embeds, wiki-links, subscripts) over everything outside code. Every pass
+/// keeps the line count, so the line numbers in `sourcepos` still match the
+/// file; columns may not, since replacements change the length of a line.
+pub fn preprocess(
+    content: &str,
+    profile: &DialectProfile,
+    base_dir: Option<&Path>,
+    embeds: &mut Embeds,
+    math: &mut Math,
+) -> String {
+    map_outside_code(content, &comrak_options(profile, false), |text| {
+        let mut text = wrap_image_paths(text);
+        if profile.math {
+            text = math.process(&text);
+        }
+        if profile.toc {
+            text = toc::normalize_markers(&text).into_owned();
+        }
+        if profile.embeds {
+            text = embeds.process(&text);
+        }
+        if profile.wikilinks {
+            text = wikilinks::process_wikilinks(&text, base_dir).into_owned();
+        }
+        if profile.subscript {
+            text = extensions::protect_subscripts(&text).into_owned();
+        }
+        text
+    })
+}
+
+pub fn comrak_options(profile: &DialectProfile, sourcepos: bool) -> ComrakOptions {
+    let mut options = ComrakOptions {
+        extension: ComrakExtensionOptions {
+            strikethrough: profile.strikethrough,
+            table: profile.table,
+            autolink: profile.autolink,
+            tasklist: profile.tasklist,
+            superscript: profile.superscript,
+            shortcodes: profile.emoji,
+            footnotes: profile.footnotes,
+            description_lists: profile.description_lists,
+            header_ids: profile.header_ids.then(String::new),
+            ..ComrakExtensionOptions::default()
+        },
+        ..ComrakOptions::default()
+    };
+    options.render.unsafe_ = profile.raw_html;
+    options.render.hardbreaks = profile.hardbreaks;
+    options.render.sourcepos = sourcepos;
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outside_code(content: &str) -> String {
+        let options = comrak_options(&Dialect::default().profile(), false);
+        map_outside_code(content, &options, |text| text.replace('$', "@"))
+    }
+
+    #[test]
+    fn skips_fenced_code() {
+        assert_eq!(outside_code("$a$\n```\n$b$\n```\n$c$\n"), "@a@\n```\n$b$\n```\n@c@\n");
+        // A shorter or different fence doesn't close the block
+        assert_eq!(outside_code("~~~~\n```\n~~~\n$b$\n~~~~\n$c$\n"), "~~~~\n```\n~~~\n$b$\n~~~~\n@c@\n");
+        assert_eq!(outside_code("> ```\n> $b$\n> ```\n$c$\n"), "> ```\n> $b$\n> ```\n@c@\n");
+    }
+
+    #[test]
+    fn skips_indented_code() {
+        assert_eq!(outside_code("para $a$\n\n    x = $a$\n\n$c$\n"), "para @a@\n\n    x = $a$\n\n@c@\n");
+        // Indented lines that continue a paragraph aren't code
+        assert_eq!(outside_code("para\n    x = $a$\n"), "para\n    x = @a@\n");
+    }
+
+    #[test]
+    fn skips_html_blocks() {
+        assert_eq!(outside_code("<pre>\n$b$\n</pre>\n$c$\n"), "<pre>\n$b$\n</pre>\n@c@\n");
+        assert_eq!(outside_code("<div>\n$b$\n\n$c$\n"), "<div>\n$b$\n\n@c@\n");
+    }
+
+    #[test]
+    fn skips_code_spans() {
+        assert_eq!(outside_code("a `$b$` c $d$"), "a `$b$` c @d@");
+        assert_eq!(outside_code("a ``$`b$`` $c$"), "a ``$`b$`` @c@");
+        assert_eq!(outside_code("- a `$b\n  $c` $d$\n"), "- a `$b\n  $c` @d@\n");
+        assert_eq!(outside_code("| `$a$` | $b$ |\n|---|---|\n"), "| `$a$` | @b@ |\n|---|---|\n");
+    }
+
+    #[test]
+    fn backticks_that_are_not_code_spans_stay_text() {
+        assert_eq!(outside_code(r"a \`$b$` $c$"), r"a \`@b@` @c@");
+        assert_eq!(outside_code("a `$b$\n\n$c$` d"), "a `@b@\n\n@c@` d");
+    }
+
+    #[test]
+    fn obsidian_syntax_is_left_alone_in_code() {
+        let profile = DialectProfile { subscript: true, ..Dialect::Obsidian.profile() };
+        let syntax = "[[Note]] ![[a.png]] ![[Note]] ==m== H~2~O";
+        let mut stack = Vec::new();
+        let mut embeds = Embeds::new(None, RenderOptions::default(), &mut stack);
+        let mut math = Math::new();
+
+        for code in [
+            format!("    {}\n", syntax),
+            format!("```\n{}\n```\n", syntax),
+            format!("<pre>\n{}\n</pre>\n", syntax),
+            format!("`{}`\n", syntax),
+        ] {
+            assert_eq!(preprocess(&code, &profile, None, &mut embeds, &mut math), code);
+        }
+        let prose = format!("{}\n", syntax);
+        assert!(!preprocess(&prose, &profile, None, &mut embeds, &mut math).contains(syntax));
+    }
+
+    #[test]
+    fn single_tildes_strike_through_unless_subscripts_are_on() {
+        let obsidian = RenderOptions { dialect: Dialect::Obsidian, ..RenderOptions::default() };
+        let html = convert_markdown("~gone~ H~2~O x^2^\n", None, obsidian);
+        assert!(html.contains(">gone</del>") && html.contains(">2</del>"));
+        assert!(!html.contains("<sub") && !html.contains("<sup"));
+
+        let scripts = RenderOptions { superscript: true, subscript: true, ..obsidian };
+        let html = convert_markdown("~~gone~~ H~2~O x^2^\n", None, scripts);
+        assert!(html.contains(">gone</del>") && !html.contains(">2</del>"));
+        assert!(html.contains("H<sub>2</sub>O") && html.contains(">2</sup>"));
+
+        let strict = RenderOptions { dialect: Dialect::Commonmark, ..scripts };
+        assert!(!strict.profile().superscript && !strict.profile().subscript);
+    }
+
+    #[test]
+    fn resolves_shortcodes_with_digits_and_plus() {
+        let html = convert_markdown(":+1: :-1: :100: :smile: :nope: `:+1:` 10:30:45\n", None, RenderOptions::default());
+        assert!(html.contains("👍 👎 💯 😄 :nope: <code"), "{}", html);
+        assert!(html.contains(">:+1:</code> 10:30:45"), "{}", html);
+    }
+}
@@ -0,0 +1,194 @@
+use regex::{Captures, Regex};
+use std::sync::LazyLock;
+
+use crate::markdown::escape_html;
+
+static PLACEHOLDER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"<span data-markpad-math="(\d+)"(?:\s+data-markpad-line)*></span>"#).unwrap());
+
+/// Pulls `$...$` and `$$...$$` math out of the Markdown before it is parsed,
+/// so emphasis, escapes and links never see the TeX, and puts it back as
+/// `<span class="math math-inline">` / `<span class="math math-display">`
+/// holding the escaped source. Only called on text outside code, so dollars
+/// in code spans and fences are left alone.
+#[derive(Default)]
+pub struct Math {
+    rendered: Vec<String>,
+}
+
+impl Math {
+    pub fn new() -> Self {
+        Math::default()
+    }
+
+    pub fn process(&mut self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+
+        while let Some(pos) = rest.find(['$', '\\']) {
+            out.push_str(&rest[..pos]);
+            rest = &rest[pos..];
+
+            // A backslash escapes the next character, so `\$` stays literal
+            // and `\\$x$` is an escaped backslash followed by math
+            if rest.starts_with('\\') {
+                let len = rest[1..].chars().next().filter(char::is_ascii_punctuation).map_or(1, |c| 1 + c.len_utf8());
+                out.push_str(&rest[..len]);
+                rest = &rest[len..];
+                continue;
+            }
+
+            if let Some((tex, len)) = display_math(rest) {
+                let line_start = &out[out.rfind('\n').map_or(0, |i| i + 1)..];
+                let lines = rest[..len].matches('\n').count();
+                out.push_str(&self.placeholder(&strip_quote_markers(tex, line_start), true, lines));
+                rest = &rest[len..];
+            } else if rest.starts_with("$$") {
+                out.push_str("$$");
+                rest = &rest[2..];
+            } else {
+                match inline_math(rest) {
+                    Ok((tex, len)) => {
+                        out.push_str(&self.placeholder(tex, false, 0));
+                        rest = &rest[len..];
+                    }
+                    Err(len) => {
+                        out.push_str(&rest[..len]);
+                        rest = &rest[len..];
+                    }
+                }
+            }
+        }
+
+        out.push_str(rest);
+        out
+    }
+
+    /// Replaces the placeholders left by `process` with the math markup.
+    pub fn splice(&self, html: &str) -> String {
+        if self.rendered.is_empty() {
+            return html.to_string();
+        }
+        PLACEHOLDER.replace_all(html, |caps: &Captures| {
+            caps[1].parse::<usize>().ok()
+                .and_then(|i| self.rendered.get(i))
+                .cloned()
+                .unwrap_or_default()
+        })
+        .into_owned()
+    }
+
+    /// Display math over several lines gets a placeholder over as many
+    /// lines, broken inside the tag so it stays in the same paragraph or list
+    /// item. This keeps the line count (see `markdown::preprocess`).
+    fn placeholder(&mut self, tex: &str, display: bool, lines: usize) -> String {
+        let kind = if display { "math-display" } else { "math-inline" };
+        self.rendered.push(format!("<span class=\"math {}\">{}</span>", kind, escape_html(tex.trim())));
+        format!("<span data-markpad-math=\"{}\"{}></span>", self.rendered.len() - 1, "\ndata-markpad-line".repeat(lines))
+    }
+}
+
+/// `$$...$$`, possibly over several lines but not across a blank line.
+/// Returns the TeX and the length of the whole match.
+fn display_math(text: &str) -> Option<(&str, usize)> {
+    let body = text.strip_prefix("$$")?;
+    let end = body.find("$$")?;
+    let tex = &body[..end];
+    if tex.trim().is_empty() || tex.contains("\n\n") {
+        return None;
+    }
+    Some((tex, end + 4))
+}
+
+/// Display math inside a blockquote repeats the `>` markers on every line;
+/// they belong to the quote, not the TeX.
+fn strip_quote_markers(tex: &str, line_start: &str) -> String {
+    let marker = line_start.trim_end();
+    if marker.is_empty() || !marker.chars().all(|c| c == '>' || c.is_whitespace()) {
+        return tex.to_string();
+    }
+    tex.split('\n')
+        .map(|line| line.trim_start().strip_prefix(marker.trim_start()).unwrap_or(line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// `$...$` on one line, following pandoc's rules so prices like `$5 and $10`
+/// stay text: no space just inside the dollars, no digit right after the
+/// closing one, and only escaped dollars in between. On failure, returns how
+/// much of `text` is plain text, so a rejected closing dollar isn't then
+/// taken as an opening one.
+fn inline_math(text: &str) -> Result<(&str, usize), usize> {
+    let body = text.strip_prefix('$').ok_or(0usize)?;
+    if body.starts_with(|c: char| c.is_whitespace() || c == '$') {
+        return Err(1);
+    }
+
+    let line = &body[..body.find('\n').unwrap_or(body.len())];
+    let mut search = 0;
+    let end = loop {
+        let end = search + line[search..].find('$').ok_or(1usize)?;
+        let backslashes = body[..end].bytes().rev().take_while(|&b| b == b'\\').count();
+        if backslashes % 2 == 0 {
+            break end;
+        }
+        search = end + 1;
+    };
+
+    let before = body[..end].chars().next_back();
+    let after = body[end + 1..].chars().next();
+    if before.is_some_and(char::is_whitespace) || after.is_some_and(|c| c.is_ascii_digit()) {
+        return Err(end + 2);
+    }
+    Ok((&body[..end], end + 2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(text: &str) -> String {
+        let mut math = Math::new();
+        let processed = math.process(text);
+        math.splice(&processed)
+    }
+
+    #[test]
+    fn renders_inline_and_display_math() {
+        assert_eq!(render("a $x^2$ b"), r#"a <span class="math math-inline">x^2</span> b"#);
+        assert_eq!(render("$$\\sum_i x_i$$"), r#"<span class="math math-display">\sum_i x_i</span>"#);
+        assert_eq!(render("$$\na < b\n$$"), "<span class=\"math math-display\">a &lt; b</span>");
+    }
+
+    #[test]
+    fn leaves_prices_and_escaped_dollars_alone() {
+        for text in ["costs $5 and $10", "$ x$", "$x $", "$x$5", r"\$x\$", "$$\n\n$$", "$$"] {
+            assert_eq!(render(text), text, "{:?}", text);
+        }
+        assert_eq!(render(r"$a\$b$"), r#"<span class="math math-inline">a\$b</span>"#);
+    }
+
+    #[test]
+    fn escaped_backslashes_do_not_escape_the_dollar() {
+        assert_eq!(render(r"\\$x$"), r#"\\<span class="math math-inline">x</span>"#);
+        assert_eq!(render(r"$a\\$ b"), r#"<span class="math math-inline">a\\</span> b"#);
+    }
+
+    #[test]
+    fn keeps_the_line_count() {
+        let mut math = Math::new();
+        assert_eq!(math.process("$$\na\nb\n$$\nafter").matches('\n').count(), 4);
+    }
+
+    #[test]
+    fn display_math_stays_in_its_paragraph_and_list_item() {
+        let html = crate::markdown::convert_markdown("- a\n  $$\n  x\n  $$\n  b\n- c\n", None, Default::default());
+        assert_eq!(html.matches("<li").count(), 2, "{}", html);
+        assert!(!html.contains("<p"), "{}", html);
+        assert!(html.contains("<span class=\"math math-display\">x</span>"), "{}", html);
+    }
+
+    #[test]
+    fn strips_blockquote_markers_from_display_math() {
+        assert_eq!(render("> $$\n> a\n> $$"), "> <span class=\"math math-display\">a</span>");
+    }
+}
@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Manager};
+
+/// Renders requested by the viewer, by tab id. The viewer numbers its
+/// requests for each tab; renders for one tab run one at a time, and a
+/// render that a newer request has overtaken is skipped if it hasn't started
+/// yet and its result dropped if it has.
+#[derive(Default)]
+pub struct RenderQueue {
+    documents: Mutex<HashMap<String, Document>>,
+}
+
+#[derive(Default)]
+struct Document {
+    latest: u64,
+    running: Arc<Mutex<()>>,
+}
+
+impl RenderQueue {
+    /// Records `generation` as the newest request for `doc_id` and returns
+    /// the lock its renders take turns on, or `None` if a newer request came
+    /// in first.
+    fn begin(&self, doc_id: &str, generation: u64) -> Option<Arc<Mutex<()>>> {
+        let mut documents = self.documents.lock().unwrap();
+        let document = documents.entry(doc_id.to_string()).or_default();
+        if generation < document.latest {
+            return None;
+        }
+        document.latest = generation;
+        Some(document.running.clone())
+    }
+
+    fn is_current(&self, doc_id: &str, generation: u64) -> bool {
+        self.documents.lock().unwrap().get(doc_id).is_none_or(|document| document.latest == generation)
+    }
+
+    pub fn forget(&self, doc_id: &str) {
+        self.documents.lock().unwrap().remove(doc_id);
+    }
+}
+
+/// Runs `job` on the blocking thread pool, so a large document doesn't hold
+/// up other commands. Returns `None` if a newer request for `doc_id` came in
+/// before `job` started or before it finished.
+pub async fn run<T, F>(app: &AppHandle, doc_id: String, generation: u64, job: F) -> Result<Option<T>, String>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let Some(running) = app.state::<RenderQueue>().begin(&doc_id, generation) else {
+        return Ok(None);
+    };
+    let app = app.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let _turn = running.lock().unwrap();
+        let queue = app.state::<RenderQueue>();
+        if !queue.is_current(&doc_id, generation) {
+            return None;
+        }
+        let result = job();
+        queue.is_current(&doc_id, generation).then_some(result)
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
@@ -0,0 +1,22 @@
+use ammonia::Builder;
+
+/// Strips everything from rendered HTML except an allowlist of tags and
+/// attributes: no scripts, event handlers, iframes, forms or `javascript:`
+/// URLs. Covers the markup Markpad itself generates (heading anchors,
+/// footnotes, task lists, wiki-links, embeds, math, alerts, the table of
+/// contents) plus the harmless HTML people write in Markdown, such as
+/// `<details>` or `<kbd>`.
+pub fn sanitize_html(html: &str) -> String {
+    Builder::default()
+        .add_tags(["section", "input"])
+        .add_generic_attributes(["class", "id", "align", "dir", "aria-hidden", "aria-label"])
+        .add_generic_attribute_prefixes(["data-"])
+        .add_tag_attributes("a", ["title"])
+        .add_tag_attributes("img", ["title"])
+        .add_tag_attributes("input", ["checked", "disabled"])
+        .add_tag_attribute_values("input", "type", ["checkbox"])
+        .add_tag_attributes("details", ["open"])
+        .add_url_schemes(["file", "asset"])
+        .clean(html)
+        .to_string()
+}
@@ -0,0 +1,175 @@
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::dialect::Dialect;
+use crate::files::{self, Backups};
+use crate::markdown::RenderOptions;
+
+const SETTINGS_FILE: &str = "settings.json";
+
+/// Rendering settings kept in the user's config folder, shared by the app
+/// and the command line.
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(default)]
+pub struct Settings {
+    /// Dialect for files without their own choice.
+    pub dialect: Dialect,
+    /// Per-file dialect, keyed by canonical path.
+    pub file_dialects: BTreeMap<String, Dialect>,
+    /// Folders whose files may use raw HTML. Everything else is sanitized.
+    pub trusted_folders: Vec<String>,
+    /// What to keep of a file's old contents when saving over it.
+    pub backups: Backups,
+    /// `^superscript^` in every dialect but CommonMark.
+    pub superscript: bool,
+    /// `~subscript~` in every dialect but CommonMark. Single tildes are then
+    /// no longer strikethrough.
+    pub subscript: bool,
+}
+
+impl Settings {
+    /// Reads the saved settings, falling back to the defaults if there are
+    /// none or they can't be read.
+    pub fn load() -> Settings {
+        settings_path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = settings_path().ok_or("No configuration folder available")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        files::save(&path, json.as_bytes(), Backups::Off)
+    }
+
+    pub fn render_options(&self, path: Option<&Path>) -> RenderOptions {
+        RenderOptions {
+            dialect: self.dialect_for(path),
+            sanitize: !path.is_some_and(|p| self.is_trusted(p)),
+            asset_urls: false,
+            superscript: self.superscript,
+            subscript: self.subscript,
+        }
+    }
+
+    pub fn dialect_for(&self, path: Option<&Path>) -> Dialect {
+        path.and_then(|p| self.file_dialect(p)).unwrap_or(self.dialect)
+    }
+
+    /// The dialect chosen for this particular file, if any.
+    pub fn file_dialect(&self, path: &Path) -> Option<Dialect> {
+        self.file_dialects.get(&file_key(path)).copied()
+    }
+
+    /// Sets the dialect of one file; `None` makes it follow the default again.
+    pub fn set_file_dialect(&mut self, path: &Path, dialect: Option<Dialect>) {
+        let key = file_key(path);
+        match dialect {
+            Some(dialect) => { self.file_dialects.insert(key, dialect); }
+            None => { self.file_dialects.remove(&key); }
+        }
+    }
+
+    /// Whether `path` is inside a trusted folder, including its subfolders.
+    pub fn is_trusted(&self, path: &Path) -> bool {
+        let path = PathBuf::from(file_key(path));
+        self.trusted_folders.iter().any(|folder| path.starts_with(folder))
+    }
+
+    /// Trusting adds `folder`; distrusting also removes any trusted folder
+    /// above it, since that trust would still cover it.
+    pub fn set_folder_trust(&mut self, folder: &Path, trusted: bool) {
+        let key = file_key(folder);
+        if trusted {
+            if !self.is_trusted(folder) {
+                self.trusted_folders.push(key);
+            }
+        } else {
+            self.trusted_folders.retain(|f| !Path::new(&key).starts_with(f));
+        }
+    }
+}
+
+/// The app's folders for settings and data.
+pub fn project_dirs() -> Option<ProjectDirs> {
+    ProjectDirs::from("com", "alecdotdev", "Markpad")
+}
+
+fn settings_path() -> Option<PathBuf> {
+    project_dirs().map(|dirs| dirs.config_dir().join(SETTINGS_FILE))
+}
+
+fn file_key(path: &Path) -> String {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()).to_string_lossy().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn files_follow_the_default_dialect_until_given_their_own() {
+        let dir = TempDir::new();
+        let mut settings = Settings::default();
+        let note = dir.path().join("note.md");
+        assert_eq!(settings.dialect_for(Some(&note)), Dialect::Markpad);
+
+        settings.dialect = Dialect::Commonmark;
+        settings.set_file_dialect(&note, Some(Dialect::Obsidian));
+        assert_eq!(settings.dialect_for(Some(&note)), Dialect::Obsidian);
+        assert_eq!(settings.dialect_for(Some(&dir.path().join("other.md"))), Dialect::Commonmark);
+        assert_eq!(settings.dialect_for(None), Dialect::Commonmark);
+
+        settings.set_file_dialect(&note, None);
+        assert_eq!(settings.file_dialect(&note), None);
+        assert_eq!(settings.dialect_for(Some(&note)), Dialect::Commonmark);
+    }
+
+    #[test]
+    fn reads_settings_with_missing_fields() {
+        let settings: Settings = serde_json::from_str(r#"{ "dialect": "obsidian" }"#).unwrap();
+        assert_eq!(settings.dialect, Dialect::Obsidian);
+        assert!(settings.file_dialects.is_empty());
+        assert_eq!(settings.backups, Backups::Off);
+
+        let json = serde_json::to_value(&settings).unwrap();
+        assert_eq!(json["dialect"], "obsidian");
+        assert_eq!(json["backups"], "off");
+    }
+
+    #[test]
+    fn trusting_a_folder_covers_its_subfolders() {
+        let dir = TempDir::new();
+        let mut settings = Settings::default();
+        let notes = dir.path().join("notes");
+        let inner = notes.join("inner");
+        settings.set_folder_trust(&notes, true);
+        settings.set_folder_trust(&inner, true);
+        assert_eq!(settings.trusted_folders.len(), 1);
+
+        assert!(settings.is_trusted(&inner.join("a.md")));
+        assert!(!settings.is_trusted(&dir.path().join("notes-other/a.md")));
+        assert!(!settings.render_options(Some(&inner.join("a.md"))).sanitize);
+        assert!(settings.render_options(Some(&dir.path().join("elsewhere/a.md"))).sanitize);
+        assert!(settings.render_options(None).sanitize);
+    }
+
+    #[test]
+    fn distrusting_a_subfolder_removes_the_trust_above_it() {
+        let dir = TempDir::new();
+        let mut settings = Settings::default();
+        let notes = dir.path().join("notes");
+        settings.set_folder_trust(&notes, true);
+        settings.set_folder_trust(&notes.join("inner"), false);
+        assert!(settings.trusted_folders.is_empty());
+        assert!(!settings.is_trusted(&notes.join("a.md")));
+    }
+}
@@ -0,0 +1,131 @@
+use comrak::nodes::{AstNode, NodeHtmlBlock, NodeValue};
+use regex::Regex;
+use std::borrow::Cow;
+use std::sync::LazyLock;
+
+use crate::analysis::{self, Heading};
+use crate::markdown::escape_html;
+
+static GITLAB_MARKER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?m)^( {0,3})\[\[_TOC_\]\]([ \t]*)$").unwrap());
+
+const TOC_MARKER: &str = "[TOC]";
+
+/// Rewrites GitLab's `[[_TOC_]]` marker to `[TOC]`, so the wiki-link pass
+/// leaves it alone and `expand` only has one form to look for.
+pub fn normalize_markers(text: &str) -> Cow<'_, str> {
+    GITLAB_MARKER.replace_all(text, "${1}[TOC]${2}")
+}
+
+/// Replaces every paragraph consisting of just `[TOC]` with a nested list of
+/// links to the document's headings.
+pub fn expand<'a>(root: &'a AstNode<'a>) {
+    if contains_marker(root) {
+        expand_with(root, &analysis::heading_tree(root));
+    }
+}
+
+/// Like `expand`, with the headings collected beforehand, for when `root` is
+/// only part of the document.
+pub fn expand_with<'a>(root: &'a AstNode<'a>, headings: &[Heading]) {
+    let markers: Vec<_> = root.descendants().filter(|node| is_marker(node)).collect();
+    if markers.is_empty() {
+        return;
+    }
+
+    let html = render_toc(headings);
+    for node in markers {
+        for child in node.children().collect::<Vec<_>>() {
+            child.detach();
+        }
+        node.data.borrow_mut().value = NodeValue::HtmlBlock(NodeHtmlBlock { block_type: 6, literal: html.clone() });
+    }
+}
+
+pub fn contains_marker<'a>(root: &'a AstNode<'a>) -> bool {
+    root.descendants().any(is_marker)
+}
+
+/// A `[TOC]` that is a link (because a `[toc]: ...` definition exists) is
+/// left as it is.
+fn is_marker<'a>(node: &'a AstNode<'a>) -> bool {
+    if !matches!(node.data.borrow().value, NodeValue::Paragraph) {
+        return false;
+    }
+    let mut text = String::new();
+    for child in node.children() {
+        match &child.data.borrow().value {
+            NodeValue::Text(t) => text.push_str(t),
+            _ => return false,
+        }
+    }
+    text.trim() == TOC_MARKER
+}
+
+fn render_toc(headings: &[Heading]) -> String {
+    let mut html = String::from("<nav class=\"table-of-contents\">\n");
+    render_list(headings, &mut html);
+    html.push_str("</nav>\n");
+    html
+}
+
+fn render_list(headings: &[Heading], html: &mut String) {
+    if headings.is_empty() {
+        return;
+    }
+    html.push_str("<ul>\n");
+    for heading in headings {
+        html.push_str(&format!("<li><a href=\"#{}\">{}</a>", escape_html(&heading.slug), escape_html(&heading.text)));
+        if !heading.children.is_empty() {
+            html.push('\n');
+            render_list(&heading.children, html);
+        }
+        html.push_str("</li>\n");
+    }
+    html.push_str("</ul>\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::Dialect;
+    use crate::markdown;
+    use comrak::{format_html, parse_document, Arena};
+
+    fn render(content: &str) -> String {
+        let options = markdown::comrak_options(&Dialect::Gfm.profile(), false);
+        let arena = Arena::new();
+        let root = parse_document(&arena, &normalize_markers(content), &options);
+        expand(root);
+        let mut html = Vec::new();
+        format_html(root, &options, &mut html).unwrap();
+        String::from_utf8(html).unwrap()
+    }
+
+    #[test]
+    fn lists_headings_as_nested_links() {
+        let html = render("[TOC]\n\n# One\n## Two & more\n## Two & more\n# Three\n");
+        assert!(html.starts_with(concat!(
+            "<nav class=\"table-of-contents\">\n<ul>\n",
+            "<li><a href=\"#one\">One</a>\n<ul>\n",
+            "<li><a href=\"#two--more\">Two &amp; more</a></li>\n",
+            "<li><a href=\"#two--more-1\">Two &amp; more</a></li>\n",
+            "</ul>\n</li>\n",
+            "<li><a href=\"#three\">Three</a></li>\n",
+            "</ul>\n</nav>\n",
+        )), "{}", html);
+        // The ids are the ones the headings get
+        assert!(html.contains("id=\"two--more-1\""));
+    }
+
+    #[test]
+    fn accepts_the_gitlab_marker() {
+        assert!(render("[[_TOC_]]\n\n# One\n").starts_with("<nav class=\"table-of-contents\">"));
+        assert_eq!(normalize_markers("  [[_TOC_]]  \ntext [[_TOC_]]"), "  [TOC]  \ntext [[_TOC_]]");
+    }
+
+    #[test]
+    fn only_a_marker_alone_in_its_paragraph_counts() {
+        assert_eq!(render("See [TOC] here\n"), "<p>See [TOC] here</p>\n");
+        assert_eq!(render("[TOC]\n\n[toc]: https://example.com\n"), "<p><a href=\"https://example.com\">TOC</a></p>\n");
+    }
+}
@@ -0,0 +1,179 @@
+use comrak::Anchorizer;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use regex::{Regex, Captures};
+use std::borrow::Cow;
+use std::fs;
+use std::path::Path;
+use std::sync::LazyLock;
+
+use crate::markdown::escape_html;
+
+pub static WIKILINK: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(!?)\[\[([^\[\]\n]+)\]\]").unwrap());
+
+/// Characters escaped when a note path is used as a link target.
+const PATH_ENCODE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Turns `[[Note]]`, `[[Note|alias]]` and `[[Note#Heading]]` links into HTML
+/// anchors. Targets are looked up relative to `base_dir`; links that don't
+/// match an existing file get the `wikilink-unresolved` class.
+pub fn process_wikilinks<'a>(text: &'a str, base_dir: Option<&Path>) -> Cow<'a, str> {
+    WIKILINK.replace_all(text, |caps: &Captures| {
+        if &caps[1] == "!" {
+            return caps[0].to_string();
+        }
+
+        let inner = &caps[2];
+        let (target, alias) = match inner.split_once('|') {
+            // `\|` is how Obsidian escapes the separator inside tables
+            Some((target, alias)) => (target.trim_end_matches('\\').trim(), Some(alias.trim())),
+            None => (inner.trim(), None),
+        };
+        let (note, heading) = match target.split_once('#') {
+            Some((note, heading)) => (note.trim(), Some(heading.trim())),
+            None => (target, None),
+        };
+
+        let anchor = heading.map(|h| format!("#{}", Anchorizer::new().anchorize(h.to_string())));
+
+        let label = match (alias, heading) {
+            (Some(alias), _) if !alias.is_empty() => alias.to_string(),
+            (_, Some(heading)) if note.is_empty() => heading.to_string(),
+            (_, Some(heading)) => format!("{} > {}", note, heading),
+            _ => note.to_string(),
+        };
+
+        if note.is_empty() {
+            return format!(
+                "<a href=\"{}\" class=\"wikilink\">{}</a>",
+                escape_html(anchor.as_deref().unwrap_or("#")),
+                escape_html(&label)
+            );
+        }
+
+        let resolved = base_dir.and_then(|dir| resolve_note(dir, note));
+        let (href, class) = match &resolved {
+            Some(rel) => (rel.clone(), "wikilink"),
+            None if has_extension(note) => (note.to_string(), "wikilink wikilink-unresolved"),
+            None => (format!("{}.md", note), "wikilink wikilink-unresolved"),
+        };
+
+        let href = format!("{}{}", utf8_percent_encode(&href, PATH_ENCODE_SET), anchor.unwrap_or_default());
+        format!("<a href=\"{}\" class=\"{}\">{}</a>", escape_html(&href), class, escape_html(&label))
+    })
+}
+
+/// Finds `note` (with or without a `.md` extension) under `dir`, comparing
+/// each path component case-insensitively. Returns the matching relative path
+/// with its on-disk casing and `/` separators.
+pub fn resolve_note(dir: &Path, note: &str) -> Option<String> {
+    let components: Vec<&str> = note.split(['/', '\\']).filter(|c| !c.is_empty() && *c != ".").collect();
+    let (file_name, folders) = components.split_last()?;
+
+    let mut current = dir.to_path_buf();
+    let mut resolved = Vec::with_capacity(components.len());
+
+    for folder in folders {
+        if *folder == ".." {
+            current.pop();
+            resolved.push("..".to_string());
+            continue;
+        }
+        let name = find_entry(&current, folder, true)?;
+        current.push(&name);
+        resolved.push(name);
+    }
+
+    let name = find_entry(&current, &format!("{}.md", file_name), false)
+        .or_else(|| find_entry(&current, file_name, false))?;
+    resolved.push(name);
+
+    Some(resolved.join("/"))
+}
+
+fn find_entry(dir: &Path, name: &str, want_dir: bool) -> Option<String> {
+    let exact = dir.join(name);
+    if (want_dir && exact.is_dir()) || (!want_dir && exact.is_file()) {
+        return Some(name.to_string());
+    }
+
+    let wanted = name.to_lowercase();
+    fs::read_dir(dir).ok()?.flatten().find_map(|entry| {
+        let entry_name = entry.file_name().to_string_lossy().to_string();
+        let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+        (is_dir == want_dir && entry_name.to_lowercase() == wanted).then_some(entry_name)
+    })
+}
+
+fn has_extension(note: &str) -> bool {
+    Path::new(note).extension().is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    fn vault() -> TempDir {
+        let dir = TempDir::new();
+        dir.write("Projects/Sub/Plan B.md", "");
+        dir.write("Index.md", "");
+        dir.write("diagram.PNG", "");
+        dir
+    }
+
+    #[test]
+    fn resolves_notes_case_insensitively() {
+        let vault = vault();
+        let dir = vault.path();
+        assert_eq!(resolve_note(dir, "index").as_deref(), Some("Index.md"));
+        assert_eq!(resolve_note(dir, "projects/sub/plan b").as_deref(), Some("Projects/Sub/Plan B.md"));
+        assert_eq!(resolve_note(dir, "Projects\\Sub\\Plan B.md").as_deref(), Some("Projects/Sub/Plan B.md"));
+        assert_eq!(resolve_note(dir, "Diagram.png").as_deref(), Some("diagram.PNG"));
+        assert_eq!(resolve_note(&dir.join("Projects"), "../index").as_deref(), Some("../Index.md"));
+        assert_eq!(resolve_note(dir, "Projects"), None);
+        assert_eq!(resolve_note(dir, "Missing"), None);
+    }
+
+    #[test]
+    fn renders_links_with_aliases_and_headings() {
+        let vault = vault();
+        let dir = vault.path();
+        assert_eq!(process_wikilinks("[[index]]", Some(dir)), r#"<a href="Index.md" class="wikilink">index</a>"#);
+        assert_eq!(
+            process_wikilinks("[[Projects/Sub/Plan B#Next Steps|the plan]]", Some(dir)),
+            r#"<a href="Projects/Sub/Plan%20B.md#next-steps" class="wikilink">the plan</a>"#
+        );
+        assert_eq!(process_wikilinks("[[Index#Set Up]]", Some(dir)), r#"<a href="Index.md#set-up" class="wikilink">Index &gt; Set Up</a>"#);
+        assert_eq!(process_wikilinks("[[#Local]]", None), r##"<a href="#local" class="wikilink">Local</a>"##);
+    }
+
+    #[test]
+    fn marks_unresolved_links() {
+        assert_eq!(process_wikilinks("[[Nope]]", None), r#"<a href="Nope.md" class="wikilink wikilink-unresolved">Nope</a>"#);
+        assert_eq!(process_wikilinks("[[file.pdf]]", None), r#"<a href="file.pdf" class="wikilink wikilink-unresolved">file.pdf</a>"#);
+    }
+
+    #[test]
+    fn escapes_targets_and_labels() {
+        assert_eq!(
+            process_wikilinks(r#"[[a"><script>|<b>x</b>]]"#, None),
+            r#"<a href="a%22%3E%3Cscript%3E.md" class="wikilink wikilink-unresolved">&lt;b&gt;x&lt;/b&gt;</a>"#
+        );
+    }
+
+    #[test]
+    fn leaves_embeds_and_escaped_pipes_to_others() {
+        assert_eq!(process_wikilinks("![[image.png]]", None), "![[image.png]]");
+        assert_eq!(process_wikilinks(r"| [[Note\|alias]] |", None), r#"| <a href="Note.md" class="wikilink wikilink-unresolved">alias</a> |"#);
+    }
+}
@@ -7,6 +7,7 @@ export class SettingsStore {
 	wordCount = $state(false);
 	renderLineHighlight = $state('none');
 	showTabs = $state(true);
+	showOutline = $state(false);
 	zenMode = $state(false);
 	preZenState = $state<{
 		renderLineHighlight: string;
@@ -27,6 +28,7 @@ export class SettingsStore {
 			const savedWordCount = localStorage.getItem('editor.wordCount');
 			const savedRenderLineHighlight = localStorage.getItem('editor.renderLineHighlight');
 			const savedShowTabs = localStorage.getItem('editor.showTabs');
+			const savedShowOutline = localStorage.getItem('editor.showOutline');
 			const savedZenMode = localStorage.getItem('editor.zenMode');
 			const savedPreZenState = localStorage.getItem('editor.preZenState');
 
@@ -39,6 +41,7 @@ export class SettingsStore {
 			if (savedWordCount !== null) this.wordCount = savedWordCount === 'true';
 			if (savedRenderLineHighlight !== null) this.renderLineHighlight = savedRenderLineHighlight;
 			if (savedShowTabs !== null) this.showTabs = savedShowTabs === 'true';
+			if (savedShowOutline !== null) this.showOutline = savedShowOutline === 'true';
 			if (savedZenMode !== null) this.zenMode = savedZenMode === 'true';
 			if (savedPreZenState !== null) {
 				try {
@@ -59,6 +62,7 @@ export class SettingsStore {
 					localStorage.setItem('editor.wordCount', String(this.wordCount));
 					localStorage.setItem('editor.renderLineHighlight', this.renderLineHighlight);
 					localStorage.setItem('editor.showTabs', String(this.showTabs));
+					localStorage.setItem('editor.showOutline', String(this.showOutline));
 					localStorage.setItem('editor.zenMode', String(this.zenMode));
 					if (this.preZenState) {
 						localStorage.setItem('editor.preZenState', JSON.stringify(this.preZenState));
@@ -102,6 +106,10 @@ export class SettingsStore {
 		this.showTabs = !this.showTabs;
 	}
 
+	toggleOutline() {
+		this.showOutline = !this.showOutline;
+	}
+
 	toggleZenMode() {
 		this.zenMode = !this.zenMode;
 		if (this.zenMode) {
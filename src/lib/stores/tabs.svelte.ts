@@ -1,3 +1,26 @@
+export type LineEnding = 'lf' | 'crlf';
+
+/** What a file on disk looked like when it was read or saved. */
+export interface FileStamp {
+	modified: number;
+	size: number;
+	hash: string;
+}
+
+/** A file as `read_file_content` returns it. */
+export interface TextFile {
+	content: string;
+	encoding: string;
+	bom: boolean;
+	line_ending: LineEnding;
+	mixed_line_endings: boolean;
+	stamp: FileStamp;
+}
+
+/** Line endings for files that don't have any yet, as the platform's own tools write them. */
+export const NATIVE_LINE_ENDING: LineEnding =
+	typeof navigator !== 'undefined' && navigator.userAgent.includes('Windows') ? 'crlf' : 'lf';
+
 export interface Tab {
 	id: string;
 	path: string;
@@ -16,6 +39,12 @@ export interface Tab {
 	isSplit: boolean;
 	splitRatio: number;
 	isScrollSynced: boolean;
+	encoding: string;
+	bom: boolean;
+	lineEnding: LineEnding;
+	mixedLineEndings: boolean;
+	/** The file as last read or saved; saving fails if it changed since. */
+	stamp: FileStamp | null;
 }
 
 class TabManager {
@@ -47,7 +76,12 @@ class TabManager {
 			anchorLine: 0,
 			isSplit: false,
 			splitRatio: 0.5,
-			isScrollSynced: false
+			isScrollSynced: false,
+			encoding: 'UTF-8',
+			bom: false,
+			lineEnding: NATIVE_LINE_ENDING,
+			mixedLineEndings: false,
+			stamp: null
 		});
 
 		this.activeTabId = id;
@@ -74,7 +108,12 @@ class TabManager {
 			anchorLine: 0,
 			isSplit: false,
 			splitRatio: 0.5,
-			isScrollSynced: false
+			isScrollSynced: false,
+			encoding: 'UTF-8',
+			bom: false,
+			lineEnding: NATIVE_LINE_ENDING,
+			mixedLineEndings: false,
+			stamp: null
 		});
 
 		this.activeTabId = id;
@@ -106,7 +145,12 @@ class TabManager {
 			anchorLine: 0,
 			isSplit: false,
 			splitRatio: 0.5,
-			isScrollSynced: false
+			isScrollSynced: false,
+			encoding: 'UTF-8',
+			bom: false,
+			lineEnding: NATIVE_LINE_ENDING,
+			mixedLineEndings: false,
+			stamp: null
 		});
 
 		this.activeTabId = id;
@@ -162,6 +206,21 @@ class TabManager {
 		}
 	}
 
+	/** Loads a file read from disk, keeping its encoding and line endings for saving. */
+	setTabFile(id: string, file: TextFile) {
+		const tab = this.tabs.find((t) => t.id === id);
+		if (tab) {
+			tab.rawContent = file.content;
+			tab.originalContent = file.content;
+			tab.encoding = file.encoding;
+			tab.bom = file.bom;
+			tab.lineEnding = file.line_ending;
+			tab.mixedLineEndings = file.mixed_line_endings;
+			tab.stamp = file.stamp;
+			tab.isDirty = false;
+		}
+	}
+
 	updateTabScroll(id: string, scrollTop: number) {
 		const tab = this.tabs.find((t) => t.id === id);
 		if (tab) {